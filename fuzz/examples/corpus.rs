@@ -0,0 +1,73 @@
+// Write seed corpus of every fuzz target, made of valid events and receipts
+// of fixture identifiers, to `corpus/<target>/`.
+use std::{fs, io, path::Path};
+
+use tda_standalone_fuzz::{instance, Fixture};
+
+// Commands accepted without connecting to other hosts.
+const COMMANDS: &[&str] = &[
+    "IDS\n",
+    "LSE\n",
+    "LSR\n",
+    "LID\n",
+    "RTH\n",
+    "HLT\n",
+    "OOB\n",
+    "ROT\n",
+    "NEW\n",
+    "IXN payload\n",
+];
+
+fn write(target: &str, inputs: &[Vec<u8>]) -> io::Result<()> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("corpus")
+        .join(target);
+    fs::create_dir_all(&dir)?;
+    for (i, input) in inputs.iter().enumerate() {
+        fs::write(dir.join(format!("seed-{}", i)), input)?;
+    }
+    println!("{}: {} inputs in {}", target, inputs.len(), dir.display());
+    Ok(())
+}
+
+fn main() -> io::Result<()> {
+    let fixture = Fixture::new();
+    // Receipts in the corpus are made for events of the fuzzed instance.
+    assert_eq!(
+        instance().primary(),
+        fixture.controller.state.prefix.to_str(),
+        "Fuzzed instance isn't the fixture controller"
+    );
+    let messages = fixture.messages();
+
+    let mut commands: Vec<Vec<u8>> = COMMANDS.iter().map(|c| c.as_bytes().to_vec()).collect();
+    commands.extend(messages.iter().cloned());
+    write("dispatcher", &commands)?;
+    write("parse_event", &messages)?;
+
+    let receipts = fixture
+        .receipts()
+        .iter()
+        .map(|rct| rct.serialize().expect("Can't serialize receipt"))
+        .collect::<Vec<_>>();
+    write("add_sig", &receipts)?;
+
+    // Responses to inception: last event of responder and its receipt,
+    // followed by receipts of witnesses.
+    let validator_kel = fixture
+        .validator
+        .log
+        .iter()
+        .map(|event| event.serialize().expect("Can't serialize event"));
+    let witness_receipts = fixture
+        .witness_receipts()
+        .iter()
+        .map(|rct| rct.serialize().expect("Can't serialize receipt"))
+        .collect::<Vec<_>>();
+    let responses = validator_kel
+        .zip(receipts.iter())
+        .map(|(event, rct)| [event, rct.clone()].concat())
+        .chain(witness_receipts.into_iter())
+        .collect::<Vec<_>>();
+    write("response", &responses)
+}
@@ -0,0 +1,14 @@
+#![no_main]
+use keri::event_message::parse;
+use libfuzzer_sys::fuzz_target;
+
+use tda_standalone_fuzz::Fixture;
+
+fuzz_target!(|data: &[u8]| {
+    let rct = match std::str::from_utf8(data).map(parse::signed_message) {
+        Ok(Ok((_, rct))) => rct,
+        _ => return,
+    };
+    let mut fixture = Fixture::new();
+    let _ = fixture.controller.add_sig(&fixture.validator.state, rct);
+});
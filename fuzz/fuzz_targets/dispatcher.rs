@@ -0,0 +1,13 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Connection ignores messages which aren't utf8.
+    if let Ok(msg) = std::str::from_utf8(data) {
+        // Commands connecting to other hosts are left out.
+        if msg.starts_with("SEN") || msg.starts_with("RES") {
+            return;
+        }
+        tda_standalone_fuzz::execute(msg);
+    }
+});
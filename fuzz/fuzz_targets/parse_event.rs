@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(event) = std::str::from_utf8(data) {
+        let mut keri = tda_standalone_fuzz::instance();
+        let primary = keri.primary().to_string();
+        keri.parse_event(&primary, event);
+    }
+});
@@ -0,0 +1,17 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use tda_standalone::receipt;
+
+// Response to `SEN`, as parsed by `send_event` and then processed.
+fuzz_target!(|data: &[u8]| {
+    let response = match std::str::from_utf8(data) {
+        Ok(response) => response,
+        Err(_) => return,
+    };
+    if let Ok((events, nontransferable)) = receipt::parse_stream(response) {
+        let mut keri = tda_standalone_fuzz::instance();
+        let primary = keri.primary().to_string();
+        keri.process_response(&primary, "127.0.0.1:0", events, nontransferable);
+    }
+});
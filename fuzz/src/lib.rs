@@ -0,0 +1,142 @@
+// Fixtures shared by fuzz targets and by the example generating their seed
+// corpus.
+use std::{fs, path::PathBuf, sync::Arc};
+
+use keri::{derivation::self_addressing::SelfAddressing, event_message::SignedEventMessage};
+
+use tda_standalone::{
+    actor::KeriHandle,
+    config::Config,
+    control::{self, Session},
+    log_state::{KeyGen, LogState},
+    metrics::Metrics,
+    receipt::Receipt,
+    rotation::Reason,
+    seed::Seed,
+    KeriInstance,
+};
+
+// Keys of fixture identifiers are derived from this phrase, so the seed
+// corpus can be generated again byte for byte.
+const PHRASE: &str = "abandon abandon abandon abandon abandon abandon \
+                      abandon abandon abandon abandon abandon about";
+
+// Two identifiers, each with inception, rotation and interaction event.
+// Validator receipts events of the controller.
+pub struct Fixture {
+    pub controller: LogState,
+    pub validator: LogState,
+    // Non-transferable identifier, receipting events of the controller too.
+    pub witness: LogState,
+}
+
+impl Fixture {
+    pub fn new() -> Fixture {
+        let seed = Seed::from_phrase(PHRASE).expect("Invalid fixture phrase");
+        let keygen = |account| KeyGen::Seed {
+            seed: seed.clone(),
+            account,
+        };
+        let identifier = |account| {
+            let mut log = LogState::new(
+                SelfAddressing::Blake3_256,
+                vec![],
+                None,
+                &[],
+                keygen(account),
+            )
+            .expect("Can't incept fixture identifier");
+            log.rotate().expect("Can't rotate fixture identifier");
+            log.make_ixn("fuzz")
+                .expect("Can't make fixture interaction");
+            log
+        };
+        Fixture {
+            controller: identifier(0),
+            validator: identifier(1),
+            witness: LogState::new_nontransferable(SelfAddressing::Blake3_256, keygen(2))
+                .expect("Can't incept fixture witness"),
+        }
+    }
+
+    // Receipts of every event of the controller, made by the validator.
+    pub fn receipts(&self) -> Vec<SignedEventMessage> {
+        self.controller
+            .log
+            .iter()
+            .filter_map(
+                |event| match self.validator.make_rct(event.event_message.clone()) {
+                    Ok(Receipt::Transferable(rct)) => Some(rct),
+                    _ => None,
+                },
+            )
+            .collect()
+    }
+
+    // Receipts of every event of the controller, made by the witness.
+    pub fn witness_receipts(&self) -> Vec<Receipt> {
+        self.controller
+            .log
+            .iter()
+            .filter_map(|event| self.witness.make_rct(event.event_message.clone()).ok())
+            .collect()
+    }
+
+    // Everything TDA can be sent by other one: events of both identifiers
+    // and receipts of the controller's events.
+    pub fn messages(&self) -> Vec<Vec<u8>> {
+        let events = self.controller.log.iter().chain(self.validator.log.iter());
+        let receipts = self.receipts().into_iter().map(Receipt::Transferable);
+        events
+            .map(|event| event.serialize())
+            .chain(
+                receipts
+                    .chain(self.witness_receipts())
+                    .map(|rct| rct.serialize()),
+            )
+            .collect::<Result<_, _>>()
+            .expect("Can't serialize fixture message")
+    }
+}
+
+// TDA whose primary identifier is the fixture controller, keeping nothing
+// on disk, so crashes replay the same way and corpus receipts target it.
+pub fn instance() -> KeriInstance {
+    let mut config = Config::default();
+    config.identifier.mnemonic_file = Some(mnemonic_file());
+    let mut keri = KeriInstance::from_config(&config, None, Arc::new(Metrics::default()))
+        .expect("Can't incept identifier");
+    let primary = keri.primary().to_string();
+    let id = keri
+        .id_mut(&primary)
+        .expect("Primary identifier is missing");
+    id.rotate(Reason::Manual)
+        .expect("Can't rotate fixture identifier");
+    id.make_ixn("fuzz").expect("Can't make fixture interaction");
+    keri
+}
+
+// Fixture phrase written where `identifier.mnemonic_file` can point to.
+fn mnemonic_file() -> PathBuf {
+    let path = std::env::temp_dir().join(format!("tda-fuzz-{}.mnemonic", std::process::id()));
+    if !path.exists() {
+        fs::write(&path, PHRASE).expect("Can't write fixture mnemonic");
+    }
+    path
+}
+
+// Execute message as if it was read from control socket connection of new
+// TDA.
+pub fn execute(msg: &str) -> Vec<u8> {
+    let keri = instance();
+    let mut session = Session::new(keri.primary().to_string(), "127.0.0.1:0".into(), true);
+    tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()
+        .expect("Can't start runtime")
+        .block_on(async {
+            let keri = KeriHandle::spawn(keri);
+            control::execute(&keri, &mut session, msg).await
+        })
+}
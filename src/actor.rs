@@ -0,0 +1,67 @@
+//! `KeriInstance` moved to its own thread, shared by connections through
+//! `KeriHandle`.
+
+use std::{
+    panic::{self, AssertUnwindSafe},
+    thread,
+};
+
+use futures::executor;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, Span};
+
+use crate::KeriInstance;
+
+// How many calls can wait for the instance before callers are held back.
+const CAPACITY: usize = 256;
+
+type Call = Box<dyn FnOnce(&mut KeriInstance) + Send>;
+
+/// Handle to KERI instance owned by its own thread. Calls made through any of
+/// its clones are applied to the instance one at a time, in order they're
+/// made, and never wait for network: whatever talks to other hosts is done
+/// by the caller between calls.
+#[derive(Clone)]
+pub struct KeriHandle(mpsc::Sender<Call>);
+
+impl KeriHandle {
+    /// Move the instance to its own thread, which runs until all handles
+    /// are dropped. Calls may block on disk, signing agent or PKCS#11 token,
+    /// so they're kept off runtime workers serving connections.
+    pub fn spawn(mut keri: KeriInstance) -> KeriHandle {
+        let (sender, mut calls) = mpsc::channel::<Call>(CAPACITY);
+        let owner = move || {
+            while let Some(call) = executor::block_on(calls.recv()) {
+                // Caller of panicking call gets no result, the others carry
+                // on as they did when instance was shared behind a lock.
+                if panic::catch_unwind(AssertUnwindSafe(|| call(&mut keri))).is_err() {
+                    error!("KERI call panicked");
+                }
+            }
+        };
+        thread::Builder::new()
+            .name("keri".into())
+            .spawn(owner)
+            .expect("Can't start KERI thread");
+        KeriHandle(sender)
+    }
+
+    /// Apply `f` to the instance and return its result. It runs within span
+    /// of the caller, so its logs are attributed to the caller's connection.
+    pub async fn call<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut KeriInstance) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (result, receiver) = oneshot::channel();
+        let span = Span::current();
+        let call: Call = Box::new(move |keri| {
+            let _entered = span.enter();
+            let _ = result.send(f(keri));
+        });
+        if self.0.clone().send(call).await.is_err() {
+            panic!("KERI instance stopped");
+        }
+        receiver.await.expect("KERI call failed")
+    }
+}
@@ -0,0 +1,545 @@
+//! Configuration of TDA, read from TOML file and validated before use.
+
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use keri::{derivation::self_addressing::SelfAddressing, prefix::BasicPrefix};
+use serde::Deserialize;
+
+use crate::{
+    hooks::{Hook, Target, Trigger},
+    rotation::Policy,
+    signer::Signer,
+    traits::ConfigTrait,
+};
+
+// Control socket, relative to working directory, unless configured otherwise.
+const DEFAULT_CONTROL_SOCKET: &str = "tda.sock";
+
+/// Error of reading, parsing or validating configuration.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// Configuration file can't be read.
+    Read(PathBuf, io::Error),
+    /// Configuration file isn't valid TOML or has unknown keys.
+    Parse(PathBuf, toml::de::Error),
+    /// Value is invalid or conflicts with other ones.
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "can't read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "can't parse {}: {}", path.display(), e),
+            ConfigError::Invalid(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Configuration of TDA, read from TOML file, see `tda.example.toml`. Missing
+/// values take defaults.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Where TDA listens for events and commands.
+    pub listen: ListenConfig,
+    /// Where KELs, receipts and keys are kept.
+    pub storage: StorageConfig,
+    /// How local identifiers are incepted.
+    pub identifier: IdentifierConfig,
+    /// Basic prefixes of witnesses declared in inception event.
+    pub witnesses: Vec<String>,
+    /// Other TDAs resolved at startup and sent automatic rotations.
+    pub peers: Vec<PeerConfig>,
+    /// How events of other identifiers are receipted.
+    pub receipts: ReceiptsConfig,
+    /// When keys are rotated automatically.
+    pub rotation: RotationConfig,
+    /// Who holds private keys and signs.
+    pub signer: SignerConfig,
+    /// Logging verbosity and format.
+    pub log: LogConfig,
+    /// Hooks notified about KERI events.
+    pub hooks: Vec<HookConfig>,
+}
+
+/// `[listen]` section: addresses TDA listens on.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenConfig {
+    /// Host TCP and HTTP ports are bound on.
+    pub host: String,
+    /// TCP port accepting KERI events, and commands if `tcp_control` is set.
+    pub port: u16,
+    /// HTTP port serving introductions, health, metrics and `/events`.
+    pub http_port: u16,
+    /// Unix socket accepting control commands, guarded by its file permissions.
+    pub control_socket: Option<PathBuf>,
+    /// Accept control commands on TCP port too. Otherwise, by default, it
+    /// takes only KERI events.
+    pub tcp_control: bool,
+}
+
+impl Default for ListenConfig {
+    fn default() -> Self {
+        ListenConfig {
+            host: "localhost".into(),
+            port: 49152,
+            http_port: 49153,
+            control_socket: Some(PathBuf::from(DEFAULT_CONTROL_SOCKET)),
+            tcp_control: false,
+        }
+    }
+}
+
+/// `[storage]` section: where state of TDA is persisted.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// Directory for KEL and receipts. Nothing is persisted if not set.
+    pub path: Option<PathBuf>,
+    /// Keystore file, defaults to `keystore.json` inside storage directory.
+    pub keystore: Option<PathBuf>,
+}
+
+/// `[identifier]` section: how local identifiers are incepted.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdentifierConfig {
+    /// Type of keys, only `Ed25519` is supported.
+    pub key_type: String,
+    /// Digest used for prefix and event digests: `Blake3_256`, `SHA3_256` or
+    /// `SHA2_256`.
+    pub digest: String,
+    /// Non-transferable identifier uses its public key as prefix, can't rotate
+    /// keys and makes non-transferable receipts.
+    pub transferable: bool,
+    /// Configuration traits declared at inception: EO (establishment-only),
+    /// DND (do-not-delegate).
+    pub traits: Vec<String>,
+    /// File with public keys committed to as next keys at inception, whose
+    /// private keys are kept outside of TDA.
+    pub next_keys: Option<PathBuf>,
+    /// File with BIP-39 mnemonic phrase, from which keys of all local
+    /// identifiers are derived. Random keys are generated if not set.
+    pub mnemonic_file: Option<PathBuf>,
+}
+
+impl Default for IdentifierConfig {
+    fn default() -> Self {
+        IdentifierConfig {
+            key_type: "Ed25519".into(),
+            digest: "Blake3_256".into(),
+            transferable: true,
+            traits: vec![],
+            next_keys: None,
+            mnemonic_file: None,
+        }
+    }
+}
+
+/// `[[peers]]` entry: other TDA known at startup.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PeerConfig {
+    /// TCP address of peer TDA, e.g. `localhost:49152`.
+    pub address: String,
+    /// Introduction url of peer, resolved once TDA listens.
+    pub oobi: Option<String>,
+}
+
+/// `[[hooks]]` entry: url or program notified about KERI events.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct HookConfig {
+    /// One of: event, receipt_threshold, remote_event.
+    pub on: String,
+    /// Url JSON notification is posted to.
+    pub url: Option<String>,
+    /// Program with arguments, run with JSON notification on its stdin.
+    pub command: Option<Vec<String>>,
+    /// Number of receipts which triggers `receipt_threshold` hook.
+    pub threshold: Option<usize>,
+    /// Only events of identifier with this prefix trigger the hook.
+    pub prefix: Option<String>,
+    /// How many times failed delivery is retried, 3 by default.
+    #[serde(default = "default_hook_retries")]
+    pub retries: u32,
+}
+
+fn default_hook_retries() -> u32 {
+    3
+}
+
+/// `[receipts]` section: how events of other identifiers are receipted.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReceiptsConfig {
+    /// Respond to events of other identifiers with receipts.
+    pub auto_receipt: bool,
+}
+
+impl Default for ReceiptsConfig {
+    fn default() -> Self {
+        ReceiptsConfig { auto_receipt: true }
+    }
+}
+
+/// `[rotation]` section: policy of automatic key rotation.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RotationConfig {
+    /// Rotate keys after given number of days.
+    pub max_age_days: Option<u64>,
+    /// Rotate keys after given number of signatures made with them.
+    pub max_signatures: Option<u64>,
+    /// How often rotation policy is checked.
+    pub check_interval_secs: u64,
+}
+
+impl Default for RotationConfig {
+    fn default() -> Self {
+        RotationConfig {
+            max_age_days: None,
+            max_signatures: None,
+            check_interval_secs: 60,
+        }
+    }
+}
+
+/// `[signer]` section: who holds private keys and signs.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SignerConfig {
+    /// `local` keeps private keys in TDA, `agent` and `pkcs11` delegate
+    /// signing to external signer.
+    pub kind: String,
+    /// Unix socket of agent process.
+    pub socket: Option<PathBuf>,
+    /// PKCS#11 module, e.g. libsofthsm2.so.
+    pub module: Option<PathBuf>,
+    /// Slot of PKCS#11 token.
+    pub slot: u64,
+    /// File with user PIN of PKCS#11 token.
+    pub pin_file: Option<PathBuf>,
+    /// Lock all memory of TDA, so private keys it holds are never swapped out.
+    pub lock_memory: bool,
+}
+
+impl Default for SignerConfig {
+    fn default() -> Self {
+        SignerConfig {
+            kind: "local".into(),
+            socket: None,
+            module: None,
+            slot: 0,
+            pin_file: None,
+            lock_memory: false,
+        }
+    }
+}
+
+/// `[log]` section: logging verbosity and format.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// One of: error, warn, info, debug, trace.
+    pub level: String,
+    /// Log line format, `text` or `json`.
+    pub format: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: "info".into(),
+            format: "text".into(),
+        }
+    }
+}
+
+impl Config {
+    /// Read configuration from TOML file. It isn't validated, see `validate`.
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let content =
+            fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+        toml::from_str(&content).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    /// Check values which can't be checked while deserializing.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.listen.host.is_empty() {
+            return Err(ConfigError::Invalid("listen.host can't be empty".into()));
+        }
+        if self.listen.port == self.listen.http_port {
+            return Err(ConfigError::Invalid(
+                "listen.port and listen.http_port must differ".into(),
+            ));
+        }
+        if self.listen.control_socket.is_none() && !self.listen.tcp_control {
+            return Err(ConfigError::Invalid(
+                "listen.control_socket or listen.tcp_control is needed to control TDA".into(),
+            ));
+        }
+        if self.listen.control_socket.is_some() && !cfg!(unix) {
+            return Err(ConfigError::Invalid(
+                "listen.control_socket requires Unix sockets, which this platform doesn't \
+                 support, use listen.tcp_control"
+                    .into(),
+            ));
+        }
+        if self.storage.keystore.is_some() && self.storage.path.is_none() {
+            return Err(ConfigError::Invalid(
+                "storage.keystore requires storage.path to be set".into(),
+            ));
+        }
+        if self.identifier.key_type != "Ed25519" {
+            return Err(ConfigError::Invalid(format!(
+                "unsupported identifier.key_type: {}, supported: Ed25519",
+                self.identifier.key_type
+            )));
+        }
+        self.digest()?;
+        self.witnesses()?;
+        self.traits()?;
+        if !self.identifier.transferable {
+            if !self.witnesses.is_empty() || self.identifier.next_keys.is_some() {
+                return Err(ConfigError::Invalid(
+                    "non-transferable identifier can't have witnesses nor next_keys".into(),
+                ));
+            }
+            if self.rotation_policy().is_enabled() {
+                return Err(ConfigError::Invalid(
+                    "non-transferable identifier can't rotate keys, remove rotation policy".into(),
+                ));
+            }
+        }
+        match (
+            self.signer.kind.as_str(),
+            &self.signer.socket,
+            &self.signer.module,
+        ) {
+            ("local", _, _) => (),
+            ("agent", Some(_), _) if cfg!(unix) => (),
+            ("agent", Some(_), _) => {
+                return Err(ConfigError::Invalid(
+                    "agent signer requires Unix sockets, which this platform doesn't support"
+                        .into(),
+                ))
+            }
+            ("agent", None, _) => {
+                return Err(ConfigError::Invalid(
+                    "agent signer requires signer.socket".into(),
+                ))
+            }
+            ("pkcs11", _, Some(_)) if self.signer.pin_file.is_some() => (),
+            ("pkcs11", _, _) => {
+                return Err(ConfigError::Invalid(
+                    "pkcs11 signer requires signer.module and signer.pin_file".into(),
+                ))
+            }
+            (kind, _, _) => {
+                return Err(ConfigError::Invalid(format!(
+                    "unknown signer.kind: {}, expected one of: local, agent, pkcs11",
+                    kind
+                )))
+            }
+        }
+        if self.signer.kind != "local" && self.identifier.mnemonic_file.is_some() {
+            return Err(ConfigError::Invalid(
+                "keys can't be both derived from mnemonic and held by external signer".into(),
+            ));
+        }
+        for peer in &self.peers {
+            if !peer.address.contains(':') {
+                return Err(ConfigError::Invalid(format!(
+                    "peer address should be host:port, got: {}",
+                    peer.address
+                )));
+            }
+        }
+        if self.rotation.max_age_days == Some(0) || self.rotation.max_signatures == Some(0) {
+            return Err(ConfigError::Invalid(
+                "rotation.max_age_days and rotation.max_signatures must be positive".into(),
+            ));
+        }
+        if self.rotation.check_interval_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "rotation.check_interval_secs must be positive".into(),
+            ));
+        }
+        self.hooks()?;
+        match self.log.format.as_str() {
+            "text" | "json" => (),
+            format => {
+                return Err(ConfigError::Invalid(format!(
+                    "unknown log.format: {}, expected one of: text, json",
+                    format
+                )))
+            }
+        }
+        match self.log.level.as_str() {
+            "error" | "warn" | "info" | "debug" | "trace" => Ok(()),
+            level => Err(ConfigError::Invalid(format!(
+                "unknown log.level: {}, expected one of: error, warn, info, debug, trace",
+                level
+            ))),
+        }
+    }
+
+    /// Hooks described by `[[hooks]]` entries.
+    pub fn hooks(&self) -> Result<Vec<Hook>, ConfigError> {
+        self.hooks
+            .iter()
+            .map(|hook| {
+                let trigger = match (hook.on.as_str(), hook.threshold) {
+                    ("event", None) => Trigger::Event,
+                    ("receipt_threshold", Some(threshold)) if threshold > 0 => {
+                        Trigger::ReceiptThreshold(threshold)
+                    }
+                    ("receipt_threshold", _) => {
+                        return Err(ConfigError::Invalid(
+                            "receipt_threshold hook requires positive threshold".into(),
+                        ))
+                    }
+                    ("remote_event", None) => Trigger::RemoteEvent,
+                    ("event", Some(_)) | ("remote_event", Some(_)) => {
+                        return Err(ConfigError::Invalid(format!(
+                            "threshold is only allowed for receipt_threshold hook, not {}",
+                            hook.on
+                        )))
+                    }
+                    (on, _) => {
+                        return Err(ConfigError::Invalid(format!(
+                            "unknown hooks.on: {}, expected one of: event, receipt_threshold, remote_event",
+                            on
+                        )))
+                    }
+                };
+                let target = match (&hook.url, &hook.command) {
+                    (Some(url), None) if url.starts_with("http://") => Target::Url(url.clone()),
+                    (Some(url), None) => {
+                        return Err(ConfigError::Invalid(format!(
+                            "hook url should start with http://, got: {}",
+                            url
+                        )))
+                    }
+                    (None, Some(command)) if !command.is_empty() => {
+                        Target::Command(command.clone())
+                    }
+                    _ => {
+                        return Err(ConfigError::Invalid(
+                            "hook requires either url or non-empty command".into(),
+                        ))
+                    }
+                };
+                Ok(Hook {
+                    trigger,
+                    prefix: hook.prefix.clone(),
+                    target,
+                    retries: hook.retries,
+                })
+            })
+            .collect()
+    }
+
+    /// Derivation used for identifier prefix and event digests.
+    pub fn digest(&self) -> Result<SelfAddressing, ConfigError> {
+        match self.identifier.digest.as_str() {
+            "Blake3_256" => Ok(SelfAddressing::Blake3_256),
+            "SHA3_256" => Ok(SelfAddressing::SHA3_256),
+            "SHA2_256" => Ok(SelfAddressing::SHA2_256),
+            digest => Err(ConfigError::Invalid(format!(
+                "unsupported identifier.digest: {}, supported: Blake3_256, SHA3_256, SHA2_256",
+                digest
+            ))),
+        }
+    }
+
+    /// Witnesses declared in inception event of local identifiers.
+    pub fn witnesses(&self) -> Result<Vec<BasicPrefix>, ConfigError> {
+        self.witnesses
+            .iter()
+            .map(|w| {
+                w.parse::<BasicPrefix>()
+                    .map_err(|_| ConfigError::Invalid(format!("invalid witness prefix: {}", w)))
+            })
+            .collect()
+    }
+
+    /// Configuration traits declared in inception event of local identifiers.
+    pub fn traits(&self) -> Result<Vec<ConfigTrait>, ConfigError> {
+        self.identifier
+            .traits
+            .iter()
+            .map(|code| {
+                ConfigTrait::from_code(code).ok_or_else(|| {
+                    ConfigError::Invalid(format!(
+                        "unknown identifier trait: {}, supported: EO, DND",
+                        code
+                    ))
+                })
+            })
+            .collect()
+    }
+
+    /// Policy of automatic rotation, disabled unless some limit is set.
+    pub fn rotation_policy(&self) -> Policy {
+        Policy {
+            max_age: self
+                .rotation
+                .max_age_days
+                .map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+            max_signatures: self.rotation.max_signatures,
+        }
+    }
+
+    /// External signer holding private keys, if configured. PKCS#11 token is
+    /// opened and logged in.
+    pub fn signer(&self) -> Result<Option<Signer>, ConfigError> {
+        match (self.signer.kind.as_str(), &self.signer.socket) {
+            ("agent", Some(socket)) => Ok(Some(Signer::Agent(socket.clone()))),
+            ("pkcs11", _) => self.pkcs11_signer().map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    #[cfg(feature = "pkcs11")]
+    fn pkcs11_signer(&self) -> Result<Signer, ConfigError> {
+        let (module, pin_file) = match (&self.signer.module, &self.signer.pin_file) {
+            (Some(module), Some(pin_file)) => (module, pin_file),
+            _ => {
+                return Err(ConfigError::Invalid(
+                    "pkcs11 signer requires signer.module and signer.pin_file".into(),
+                ))
+            }
+        };
+        let pin =
+            fs::read_to_string(pin_file).map_err(|e| ConfigError::Read(pin_file.clone(), e))?;
+        let token = crate::pkcs11::Token::open(module, self.signer.slot, pin.trim())
+            .map_err(|e| ConfigError::Invalid(e.to_string()))?;
+        Ok(Signer::Pkcs11(std::sync::Arc::new(token)))
+    }
+
+    #[cfg(not(feature = "pkcs11"))]
+    fn pkcs11_signer(&self) -> Result<Signer, ConfigError> {
+        Err(ConfigError::Invalid(
+            "pkcs11 signer requires TDA built with pkcs11 feature".into(),
+        ))
+    }
+
+    /// Keystore file, if keys are persisted.
+    pub fn keystore_path(&self) -> Option<PathBuf> {
+        match (&self.storage.keystore, &self.storage.path) {
+            (Some(keystore), _) => Some(keystore.clone()),
+            (None, Some(path)) => Some(path.join("keystore.json")),
+            (None, None) => None,
+        }
+    }
+}
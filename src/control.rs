@@ -0,0 +1,651 @@
+//! Connections to TDA: KERI events from peers and control commands from
+//! the operator, over TCP or the control socket.
+
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::{io, path::Path, str::from_utf8, sync::Arc, time::Instant};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use keri::{error::Error as KeriError, event_message::SignedEventMessage};
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+use crate::{
+    actor::KeriHandle, config, keystore, log_state, logging, metrics, oobi, receipt, rotation,
+    seal, send_event, send_receipt, signer,
+};
+
+/// State of one connection to TDA: identifier control commands act on and
+/// whether they are accepted at all.
+pub struct Session {
+    /// Primary identifier of TDA.
+    pub primary: String,
+    /// Identifier selected with `SEL`, primary one by default.
+    pub selected: String,
+    /// HTTP address introduction urls are made with.
+    pub http_address: String,
+    /// Control commands are accepted, not only KERI events.
+    pub control: bool,
+}
+
+impl Session {
+    /// Session with primary identifier selected.
+    pub fn new(primary: String, http_address: String, control: bool) -> Self {
+        Session {
+            selected: primary.clone(),
+            primary,
+            http_address,
+            control,
+        }
+    }
+}
+
+// Longest message accepted on a connection. Peer which sends more without
+// completing it is disconnected.
+const MAX_MESSAGE: usize = 64 * 1024;
+
+/// Serve KERI events and, if session accepts them, control commands sent
+/// over given connection. Data is read until it makes a complete message, so
+/// events with many keys or receipts aren't cut at read boundary.
+pub async fn handle_connection<S>(
+    mut socket: S,
+    keri: KeriHandle,
+    mut session: Session,
+    _connection: metrics::ConnectionGuard,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buf = [0; 1024];
+    let mut pending = vec![];
+
+    // In a loop, read data from the socket
+    loop {
+        let n = match socket.read(&mut buf).await {
+            Ok(n) => n,
+            Err(e) => {
+                warn!("Can't read from connection, closing it: {}", e);
+                return;
+            }
+        };
+        let eof = n == 0;
+        pending.extend_from_slice(&buf[..n]);
+        while let Some(len) = complete_message(&pending, eof) {
+            let msg: Vec<u8> = pending.drain(..len).collect();
+            // Read message as utf string
+            let msg = match from_utf8(&msg) {
+                Ok(msg) => msg,
+                Err(_) => {
+                    warn!("Ignoring message which isn't utf8");
+                    continue;
+                }
+            };
+            let response = execute(&keri, &mut session, msg).await;
+            if !response.is_empty() {
+                if let Err(e) = socket.write_all(&response).await {
+                    warn!("Can't write to connection, closing it: {}", e);
+                    return;
+                }
+            }
+        }
+        if eof {
+            return;
+        }
+        if pending.len() > MAX_MESSAGE {
+            warn!(size = pending.len(), "Message too long, closing connection");
+            return;
+        }
+    }
+}
+
+// Length of the first complete message at the start of `pending`: command
+// up to and including its newline, or KERI events once all of them parse.
+// At the end of input, whatever is left makes the message.
+fn complete_message(pending: &[u8], eof: bool) -> Option<usize> {
+    if pending.is_empty() {
+        return None;
+    }
+    if eof {
+        return Some(pending.len());
+    }
+    let text = match from_utf8(pending) {
+        Ok(text) => text,
+        // Multibyte character may be split between reads.
+        Err(e) if e.error_len().is_none() => return None,
+        Err(_) => return Some(pending.len()),
+    };
+    if text.trim_start().starts_with('{') {
+        receipt::parse_stream(text).ok().map(|_| pending.len())
+    } else {
+        text.find('\n').map(|i| i + 1)
+    }
+}
+
+/// Process message read from connection: KERI event of other TDA or control
+/// command. Returns response to write back, empty if there's none.
+pub async fn execute(keri: &KeriHandle, session: &mut Session, msg: &str) -> Vec<u8> {
+    let mut response = vec![];
+    // Ignore messages shorted then 4 bytes
+    if msg.len() < 4 {
+        return response;
+    }
+    // Read first 4 characters to see if it match with TDA commands
+    let command = msg.get(0..3).unwrap_or_default();
+    // KERI events are JSON objects, everything else is command.
+    if !command.starts_with('{') {
+        info!(command = logging::command_name(msg), "Received command");
+        if !session.control {
+            response.extend_from_slice(b"Control commands are accepted only on control socket\n");
+            return response;
+        }
+    }
+    // Calls on KERI instance run on its own thread, so they take a copy of
+    // selected prefix instead of borrowing the session.
+    let selected = session.selected.clone();
+    match command {
+        "IDS" => {
+            let msg = keri
+                .call(move |keri| match keri.id(&selected) {
+                    Ok(id) => {
+                        let mut msg = format!("Prefix: {}\nSN: {}\n", id.prefix(), id.log.state.sn);
+                        if id.log.is_abandoned() {
+                            msg.push_str("Abandoned\n");
+                        }
+                        let traits = id.log.traits();
+                        if !traits.is_empty() {
+                            let codes: Vec<&str> = traits.iter().map(|t| t.code()).collect();
+                            msg.push_str(&format!("Traits: {}\n", codes.join(", ")));
+                        }
+                        msg
+                    }
+                    Err(e) => format!("{}\n", e),
+                })
+                .await;
+
+            response.extend_from_slice(msg.as_bytes());
+        }
+        "LSE" => {
+            let kel: Vec<SignedEventMessage> = keri
+                .call(move |keri| {
+                    keri.id(&selected)
+                        .map(|id| id.log.log.clone())
+                        .unwrap_or_default()
+                })
+                .await;
+            for signed_message in &kel {
+                let msg = format!("{:?}\n", &signed_message.event_message.event.event_data);
+
+                response.extend_from_slice(msg.as_bytes());
+            }
+        }
+        "LSR" => {
+            let kerl = keri
+                .call(move |keri| {
+                    keri.id(&selected)
+                        .map(|id| id.log.sigs_map.clone())
+                        .unwrap_or_default()
+                })
+                .await;
+
+            for (key, val) in &kerl {
+                let msg = format!("{}: {:?}\n", key, val);
+                response.extend_from_slice(msg.as_bytes());
+            }
+        }
+        "SEN" => {
+            // Simple parsing of the command
+            let mut iter = msg.split_whitespace();
+            iter.next();
+            // Get host to where send the message
+            let (host, port) = match (iter.next(), iter.next()) {
+                (Some(host), Some(port)) => (host, port),
+                _ => {
+                    response.extend_from_slice(b"Cannot parse the address\n");
+                    return response;
+                }
+            };
+            let address = [host, ":", port].concat();
+            info!(peer = %address, "Send my events");
+            let last_event = keri
+                .call(move |keri| {
+                    keri.id(&selected).and_then(|id| {
+                        id.log
+                            .log
+                            .last()
+                            .cloned()
+                            .ok_or_else(|| KeriError::SemanticError("local KEL is empty".into()))
+                    })
+                })
+                .await;
+            let last_event = match last_event {
+                Ok(last_event) => last_event,
+                Err(e) => {
+                    let msg = format!("Can't send event: {}\n", e);
+                    response.extend_from_slice(msg.as_bytes());
+                    return response;
+                }
+            };
+
+            // We can get more than one event in response.
+            // Not only receipt events, but also other
+            // types. Other clients are served while we wait for it.
+            let sent = Instant::now();
+            let sent_event = send_event(address.clone(), last_event).await;
+            let elapsed = sent.elapsed();
+            match sent_event {
+                Ok((events, nontransferable)) => {
+                    debug!(count = events.len(), "Got response events");
+                    let (selected, peer) = (session.selected.clone(), address.clone());
+                    let receipts = keri
+                        .call(move |keri| {
+                            keri.metrics.sen_finished(elapsed);
+                            keri.process_response(&selected, &peer, events, nontransferable)
+                        })
+                        .await;
+                    for rct in receipts {
+                        if let Err(e) = send_receipt(address.clone(), rct).await {
+                            warn!(peer = %address, "Can't send receipt: {}", e);
+                        }
+                    }
+                }
+                Err(e) => {
+                    keri.call(move |keri| keri.metrics.sen_finished(elapsed))
+                        .await;
+                    let msg = format!("Can't send event: {}\n", e);
+                    response.extend_from_slice(msg.as_bytes());
+                }
+            }
+        }
+        "ROT" => {
+            let (seals, args): (Vec<&str>, Vec<&str>) = msg
+                .split_whitespace()
+                .skip(1)
+                .partition(|arg| seal::is_seal(arg));
+            let seals: Vec<String> = seals.into_iter().map(String::from).collect();
+            let rotated = match parse_rotation_args(args.into_iter()) {
+                Ok((imported, next)) => {
+                    keri.call(move |keri| {
+                        keri.id_mut(&selected).and_then(|id| {
+                            let seals: Vec<&str> = seals.iter().map(String::as_str).collect();
+                            let seals = id.parse_seals(&seals)?;
+                            id.rotate_with(rotation::Reason::Manual, imported, next, seals)
+                        })
+                    })
+                    .await
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = rotated {
+                let msg = format!("Can't rotate: {}\n", e);
+                response.extend_from_slice(msg.as_bytes());
+            }
+        }
+        "ABN" => {
+            let args = msg.split_whitespace().skip(1);
+            let abandoned = match parse_rotation_args(args) {
+                Ok((imported, None)) => {
+                    keri.call(move |keri| {
+                        keri.id_mut(&selected).and_then(|id| id.abandon(imported))
+                    })
+                    .await
+                }
+                Ok((_, Some(_))) => Err(KeriError::SemanticError(
+                    "abandoned identifier has no next keys".into(),
+                )),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = abandoned {
+                let msg = format!("Can't abandon: {}\n", e);
+                response.extend_from_slice(msg.as_bytes());
+            }
+        }
+        "REC" => {
+            let mut args = msg.split_whitespace().skip(1);
+            let sn = args.next().and_then(|sn| sn.parse::<u64>().ok());
+            let recovered = match (sn, parse_rotation_args(args)) {
+                (Some(sn), Ok((imported, next))) => {
+                    keri.call(move |keri| {
+                        keri.id_mut(&selected)
+                            .and_then(|id| id.recover(sn, imported, next))
+                    })
+                    .await
+                }
+                (None, _) => Err(KeriError::SemanticError(
+                    "expected sn of first superseded event".into(),
+                )),
+                (_, Err(e)) => Err(e),
+            };
+            if let Err(e) = recovered {
+                let msg = format!("Can't recover: {}\n", e);
+                response.extend_from_slice(msg.as_bytes());
+            }
+        }
+        "IXN" => {
+            let args: Vec<String> = msg.split_whitespace().skip(1).map(String::from).collect();
+            // Either seals or payload to anchor digest of, which is all the
+            // rest of the command
+            if args.is_empty() {
+                response.extend_from_slice(b"Cannot parse the payload\n");
+                return response;
+            }
+            let seals = args.iter().filter(|arg| seal::is_seal(arg)).count();
+            if seals > 0 && seals < args.len() {
+                response.extend_from_slice(b"Can't mix payload with seals\n");
+                return response;
+            }
+            let payload = msg[3..].trim().to_string();
+            let made = keri
+                .call(move |keri| {
+                    keri.id_mut(&selected).and_then(|id| {
+                        if seals > 0 {
+                            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                            let seals = id.parse_seals(&args)?;
+                            id.anchor(seals)
+                        } else {
+                            id.make_ixn(&payload)
+                        }
+                    })
+                })
+                .await;
+            if let Err(e) = made {
+                let msg = format!("Can't make interaction: {}\n", e);
+                response.extend_from_slice(msg.as_bytes());
+            }
+        }
+        "RTH" => {
+            let rotations = keri
+                .call(move |keri| {
+                    keri.id(&selected)
+                        .map(|id| id.rotations.clone())
+                        .unwrap_or_default()
+                })
+                .await;
+            for record in &rotations.records {
+                let msg = format!(
+                    "{}: {:?} at {}\n",
+                    record.sn, record.reason, record.timestamp
+                );
+                response.extend_from_slice(msg.as_bytes());
+            }
+            let msg = format!("Signatures with current keys: {}\n", rotations.signatures);
+            response.extend_from_slice(msg.as_bytes());
+        }
+        "NEW" => {
+            let args = msg.split_whitespace().skip(1);
+            let incepted = match parse_rotation_args(args) {
+                Ok((imported, next)) if imported.is_empty() => {
+                    keri.call(move |keri| keri.incept(next)).await
+                }
+                Ok(_) => Err(KeriError::SemanticError(
+                    "new identifier can't import keys".into(),
+                )),
+                Err(e) => Err(e),
+            };
+            let msg = match incepted {
+                Ok(prefix) => format!("Incepted {}\n", prefix),
+                Err(e) => format!("Can't incept: {}\n", e),
+            };
+            response.extend_from_slice(msg.as_bytes());
+        }
+        "LID" => {
+            let msg = keri
+                .call(move |keri| {
+                    let mut msg = String::new();
+                    for (prefix, id) in &keri.ids {
+                        msg.push_str(&format!(
+                            "{} {} SN: {}",
+                            if *prefix == selected { "*" } else { " " },
+                            prefix,
+                            id.log.state.sn
+                        ));
+                        if *prefix == keri.primary {
+                            msg.push_str(" (primary)");
+                        }
+                        msg.push('\n');
+                    }
+                    msg
+                })
+                .await;
+            response.extend_from_slice(msg.as_bytes());
+        }
+        "SEL" => {
+            let prefix = msg
+                .split_whitespace()
+                .nth(1)
+                .unwrap_or_default()
+                .to_string();
+            let known = {
+                let prefix = prefix.clone();
+                keri.call(move |keri| keri.id(&prefix).map(|_| ())).await
+            };
+            let msg = match known {
+                Ok(()) => {
+                    let msg = format!("Selected {}\n", prefix);
+                    session.selected = prefix;
+                    msg
+                }
+                Err(e) => format!("Can't select: {}\n", e),
+            };
+            response.extend_from_slice(msg.as_bytes());
+        }
+        "DEL" => {
+            let prefix = msg
+                .split_whitespace()
+                .nth(1)
+                .unwrap_or_default()
+                .to_string();
+            let deleted = {
+                let prefix = prefix.clone();
+                keri.call(move |keri| keri.delete(&prefix)).await
+            };
+            let msg = match deleted {
+                Ok(()) => {
+                    if prefix == session.selected {
+                        session.selected = session.primary.clone();
+                    }
+                    format!("Deleted {}\n", prefix)
+                }
+                Err(e) => format!("Can't delete: {}\n", e),
+            };
+            response.extend_from_slice(msg.as_bytes());
+        }
+        "HLT" => {
+            let report = keri.call(|keri| keri.health()).await;
+            let mut msg = report.to_json();
+            msg.push(b'\n');
+            response.extend_from_slice(&msg);
+        }
+        "OOB" => {
+            let oobi = oobi::Oobi::new(session.selected.clone(), oobi::CONTROLLER_ROLE);
+            let msg = format!("{}\n", oobi.url(&session.http_address));
+            response.extend_from_slice(msg.as_bytes());
+        }
+        "RES" => {
+            let mut iter = msg.split_whitespace();
+            iter.next();
+            // Get introduction url
+            let url = iter.next().unwrap_or_default().to_string();
+            info!(url = %url, "Resolve introduction");
+            let msg = match oobi::Oobi::from_url(&url) {
+                Some(introduction) => match oobi::resolve(&url).await {
+                    Ok(kel) => {
+                        keri.call(
+                            move |keri| match keri.process_kel(&introduction.prefix, &kel) {
+                                Ok(state) => {
+                                    format!("Resolved {}, SN: {}\n", introduction.prefix, state.sn)
+                                }
+                                Err(e) => format!("Can't verify KEL: {}\n", e),
+                            },
+                        )
+                        .await
+                    }
+                    Err(e) => format!("Can't fetch KEL: {}\n", e),
+                },
+                None => "Cannot parse the introduction url\n".to_string(),
+            };
+            response.extend_from_slice(msg.as_bytes());
+        }
+        // If we do not match any command then probably we are getting keri events
+        _ => {
+            debug!("KERI event message. Processing");
+            let event = msg.to_string();
+            let receipt = keri
+                .call(move |keri| keri.parse_event(&selected, &event))
+                .await;
+            debug!(bytes = receipt.len(), "Respond to event");
+            // Send back the receipt
+
+            response.extend_from_slice(&receipt);
+        }
+    }
+    response
+}
+
+/// File of bound control socket. It's removed when dropped, so the socket
+/// doesn't outlive TDA serving it.
+#[cfg(unix)]
+pub struct SocketFile(PathBuf);
+
+#[cfg(unix)]
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            warn!(socket = %self.0.display(), "Can't remove control socket: {}", e);
+        }
+    }
+}
+
+/// Bind control socket readable and writable only by its owner. Socket left
+/// over from previous run is replaced, but one still accepting connections
+/// belongs to running TDA and is refused.
+#[cfg(unix)]
+pub fn bind_control_socket(path: &Path) -> io::Result<(UnixListener, SocketFile)> {
+    use std::{
+        fs,
+        os::unix::fs::{DirBuilderExt, PermissionsExt},
+    };
+
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "control socket is used by another running TDA",
+        ));
+    }
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "control socket has no file name",
+        )
+    })?;
+    // Socket is bound in a directory only we can enter, so nobody can
+    // connect before its permissions are restricted, and only then it's
+    // moved in place.
+    let mut private = std::ffi::OsString::from(".");
+    private.push(name);
+    private.push(format!(".{}", std::process::id()));
+    let private = path.with_file_name(private);
+    match fs::remove_dir_all(&private) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => (),
+    }
+    fs::DirBuilder::new().mode(0o700).create(&private)?;
+    let bound = private.join("socket");
+    let listener = UnixListener::bind(&bound).and_then(|listener| {
+        fs::set_permissions(&bound, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&bound, path)?;
+        Ok((listener, SocketFile(path.to_path_buf())))
+    });
+    let _ = fs::remove_dir_all(&private);
+    listener
+}
+
+/// Accept connections on control socket, each with control commands allowed.
+#[cfg(unix)]
+pub async fn serve_control_socket(
+    mut listener: UnixListener,
+    path: PathBuf,
+    keri: KeriHandle,
+    primary: String,
+    http_address: String,
+    metrics: Arc<metrics::Metrics>,
+) {
+    loop {
+        let socket = match listener.accept().await {
+            Ok((socket, _)) => socket,
+            Err(e) => {
+                error!(socket = %path.display(), "Control socket failed: {}", e);
+                return;
+            }
+        };
+        let connection = metrics::ConnectionGuard::new(Arc::clone(&metrics));
+        let span = info_span!(
+            "connection",
+            peer = %path.display(),
+            prefix = tracing::field::Empty
+        );
+        let session = Session::new(primary.clone(), http_address.clone(), true);
+        tokio::spawn(handle_connection(socket, keri.clone(), session, connection).instrument(span));
+    }
+}
+
+/// Send control command to running TDA and return its response. Control
+/// socket is used when configured, TCP port otherwise.
+pub async fn send_command(listen: &config::ListenConfig, command: &str) -> io::Result<Vec<u8>> {
+    let command = format!("{}\n", command);
+    match &listen.control_socket {
+        #[cfg(unix)]
+        Some(path) => exchange(UnixStream::connect(path).await?, &command).await,
+        #[cfg(not(unix))]
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::Other,
+            "control socket requires Unix sockets, which this platform doesn't support",
+        )),
+        None => {
+            let address = format!("{}:{}", listen.host, listen.port);
+            exchange(TcpStream::connect(address).await?, &command).await
+        }
+    }
+}
+
+// Write command and read response until TDA closes the connection, which it
+// does once it sees we're done writing.
+async fn exchange<S>(mut stream: S, command: &str) -> io::Result<Vec<u8>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(command.as_bytes()).await?;
+    stream.shutdown().await?;
+    let mut response = vec![];
+    stream.read_to_end(&mut response).await?;
+    Ok(response)
+}
+
+/// Parse `[import=<keypairs file>] [next=<public keys file>]` arguments of
+/// `ROT` and `REC` commands.
+pub fn parse_rotation_args<'a>(
+    args: impl Iterator<Item = &'a str>,
+) -> Result<(Vec<signer::Keypair>, Option<log_state::ExternalKeys>), KeriError> {
+    let mut imported = vec![];
+    let mut next = None;
+    for arg in args {
+        let mut parts = arg.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some("import"), Some(path)) => {
+                imported.extend(keystore::read_keypairs(Path::new(path))?)
+            }
+            (Some("next"), Some(path)) => {
+                next = Some(keystore::read_external_keys(Path::new(path))?)
+            }
+            _ => {
+                return Err(KeriError::SemanticError(format!(
+                    "unknown argument: {}",
+                    arg
+                )))
+            }
+        }
+    }
+    Ok((imported, next))
+}
@@ -0,0 +1,232 @@
+//! Notifications of KEL and receipt changes, streamed from `/events`
+//! endpoint and delivered to hooks.
+
+use keri::{event_message::SignedEventMessage, prefix::Prefix, state::IdentifierState};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::{logging, receipt::NontransferableReceipt};
+
+// How many notifications slow subscriber can fall behind before it misses
+// some of them.
+const CAPACITY: usize = 256;
+
+/// Change of KEL or receipts, streamed to subscribers of `/events` endpoint.
+#[derive(Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Notification {
+    /// Local identifier made new event.
+    Event {
+        /// Prefix of the identifier.
+        prefix: String,
+        /// Sequence number of the event.
+        sn: u64,
+        /// Type of the event, e.g. `Rot`.
+        event_type: &'static str,
+    },
+    /// Receipt of event of local identifier was verified and added to its
+    /// sigs_map, which now holds `receipts` receipts of the event.
+    Receipt {
+        /// Prefix of the identifier whose event is receipted.
+        prefix: String,
+        /// Sequence number of the receipted event.
+        sn: u64,
+        /// Prefixes of identifiers which made the receipt.
+        validators: Vec<String>,
+        /// Receipts of the event stored so far.
+        receipts: usize,
+    },
+    /// Receipt was escrowed until validator's establishment event is known.
+    EscrowedReceipt {
+        /// Prefix of the identifier whose event is receipted.
+        prefix: String,
+        /// Sequence number of the receipted event.
+        sn: u64,
+        /// Prefixes of identifiers which made the receipt.
+        validators: Vec<String>,
+    },
+    /// State of other identifier changed.
+    RemoteState {
+        /// Prefix of the identifier.
+        prefix: String,
+        /// Sequence number of its last accepted event.
+        sn: u64,
+    },
+    /// KEL of other identifier was resolved from its introduction, replacing
+    /// what was known about it.
+    Resolved {
+        /// Prefix of the identifier.
+        prefix: String,
+        /// Sequence number of its last resolved event.
+        sn: u64,
+    },
+}
+
+impl Notification {
+    /// Event made by local identifier.
+    pub fn event(event: &SignedEventMessage) -> Self {
+        Notification::Event {
+            prefix: event.event_message.event.prefix.to_str(),
+            sn: event.event_message.event.sn,
+            event_type: logging::event_type(event),
+        }
+    }
+
+    /// Receipt made before it's added, see `counted` and `escrowed`.
+    pub fn receipt(validator: &IdentifierState, rct: &SignedEventMessage) -> Self {
+        Notification::Receipt {
+            prefix: rct.event_message.event.prefix.to_str(),
+            sn: rct.event_message.event.sn,
+            validators: vec![validator.prefix.to_str()],
+            receipts: 0,
+        }
+    }
+
+    /// Same receipt, with number of receipts of the event once it's added.
+    pub fn counted(mut self, count: usize) -> Self {
+        if let Notification::Receipt { receipts, .. } = &mut self {
+            *receipts = count;
+        }
+        self
+    }
+
+    /// Same receipt, escrowed instead of stored.
+    pub fn escrowed(self) -> Self {
+        match self {
+            Notification::Receipt {
+                prefix,
+                sn,
+                validators,
+                ..
+            } => Notification::EscrowedReceipt {
+                prefix,
+                sn,
+                validators,
+            },
+            other => other,
+        }
+    }
+
+    /// Receipt made by nontransferable identifiers, before it's added.
+    pub fn nontransferable_receipt(rct: &NontransferableReceipt) -> Self {
+        Notification::Receipt {
+            prefix: rct.body.event.prefix.to_str(),
+            sn: rct.body.event.sn,
+            validators: rct
+                .couplets
+                .iter()
+                .map(|(validator, _)| validator.to_str())
+                .collect(),
+            receipts: 0,
+        }
+    }
+
+    /// New state of other identifier.
+    pub fn remote_state(state: &IdentifierState) -> Self {
+        Notification::RemoteState {
+            prefix: state.prefix.to_str(),
+            sn: state.sn,
+        }
+    }
+
+    /// State of other identifier resolved from its introduction.
+    pub fn resolved(state: &IdentifierState) -> Self {
+        Notification::Resolved {
+            prefix: state.prefix.to_str(),
+            sn: state.sn,
+        }
+    }
+
+    /// Kind of notification, as given in its JSON and `kind` filter.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Notification::Event { .. } => "event",
+            Notification::Receipt { .. } => "receipt",
+            Notification::EscrowedReceipt { .. } => "escrowed_receipt",
+            Notification::RemoteState { .. } => "remote_state",
+            Notification::Resolved { .. } => "resolved",
+        }
+    }
+
+    /// Prefix of identifier the notification is about.
+    pub fn prefix(&self) -> &str {
+        match self {
+            Notification::Event { prefix, .. }
+            | Notification::Receipt { prefix, .. }
+            | Notification::EscrowedReceipt { prefix, .. }
+            | Notification::RemoteState { prefix, .. }
+            | Notification::Resolved { prefix, .. } => prefix,
+        }
+    }
+
+    /// Notification as single line of JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Can't serialize notification")
+    }
+}
+
+/// Channel notifications are published to. Publishing without subscribers
+/// drops the notification.
+#[derive(Clone)]
+pub struct Bus(broadcast::Sender<Notification>);
+
+impl Default for Bus {
+    fn default() -> Self {
+        Bus(broadcast::channel(CAPACITY).0)
+    }
+}
+
+impl Bus {
+    /// Send notification to all current subscribers.
+    pub fn publish(&self, notification: Notification) {
+        let _ = self.0.send(notification);
+    }
+
+    /// Receive notifications published from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Notification> {
+        self.0.subscribe()
+    }
+}
+
+/// Kinds and prefixes subscriber is interested in, given in query of
+/// `/events` url, e.g. `?kind=event,receipt&prefix=<prefix>`. Empty list
+/// matches everything.
+#[derive(Default)]
+pub struct Filter {
+    kinds: Vec<String>,
+    prefixes: Vec<String>,
+}
+
+impl Filter {
+    /// Parse query part of url, without `?`. Unknown keys are ignored.
+    pub fn from_query(query: &str) -> Self {
+        let mut filter = Filter::default();
+        for pair in query.split('&') {
+            let mut parts = pair.splitn(2, '=');
+            let values = |values: Option<&str>| -> Vec<String> {
+                values
+                    .unwrap_or_default()
+                    .split(',')
+                    .filter(|value| !value.is_empty())
+                    .map(String::from)
+                    .collect()
+            };
+            match parts.next() {
+                Some("kind") => filter.kinds.extend(values(parts.next())),
+                Some("prefix") => filter.prefixes.extend(values(parts.next())),
+                _ => (),
+            }
+        }
+        filter
+    }
+
+    /// Whether subscriber is interested in the notification.
+    pub fn matches(&self, notification: &Notification) -> bool {
+        (self.kinds.is_empty() || self.kinds.iter().any(|kind| kind == notification.kind()))
+            && (self.prefixes.is_empty()
+                || self
+                    .prefixes
+                    .iter()
+                    .any(|prefix| prefix == notification.prefix()))
+    }
+}
@@ -0,0 +1,38 @@
+//! Health report of TDA components.
+
+use serde::Serialize;
+
+/// State of TDA components, reported by `/health` and `/ready` endpoints and
+/// `HLT` command.
+#[derive(Serialize)]
+pub struct Report {
+    /// TCP listener for events and commands is bound.
+    pub listening: bool,
+    /// Storage directory is accessible, or storage isn't configured.
+    pub storage_available: bool,
+    /// Private keys are loaded and keystore file is accessible, if configured.
+    pub keystore_unlocked: bool,
+    /// Receipts waiting for validator's establishment event.
+    pub escrowed_receipts: usize,
+    /// Local KEL verifies from inception to the current state.
+    pub kel_verified: bool,
+    /// Sequence number of the last event of primary identifier.
+    pub sn: u64,
+}
+
+impl Report {
+    /// TDA runs and accepts connections.
+    pub fn alive(&self) -> bool {
+        self.listening
+    }
+
+    /// All components work, so TDA can serve requests.
+    pub fn ready(&self) -> bool {
+        self.listening && self.storage_available && self.keystore_unlocked && self.kel_verified
+    }
+
+    /// Report as JSON object.
+    pub fn to_json(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("Can't serialize health report")
+    }
+}
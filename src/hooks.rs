@@ -0,0 +1,203 @@
+//! Hooks notifying programs or urls of events, with retries and delivery
+//! log.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use tokio::{fs::OpenOptions, io::AsyncWriteExt, process::Command, sync::broadcast};
+use tracing::{info, warn};
+
+use crate::{events::Notification, http};
+
+// Delay before first retry, doubled with each next one.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// What the hook is triggered by.
+#[derive(Clone, Debug)]
+pub enum Trigger {
+    /// Local identifier made new event.
+    Event,
+    /// Event of local identifier got given number of receipts.
+    ReceiptThreshold(usize),
+    /// Event of other identifier was accepted.
+    RemoteEvent,
+}
+
+/// Where the notification is delivered.
+#[derive(Clone, Debug)]
+pub enum Target {
+    /// POST JSON notification to the url.
+    Url(String),
+    /// Run program with arguments, JSON notification given on its stdin.
+    Command(Vec<String>),
+}
+
+/// Program or url notified of events, configured in `[[hooks]]` section.
+#[derive(Clone, Debug)]
+pub struct Hook {
+    /// Kind of notifications which trigger the hook.
+    pub trigger: Trigger,
+    /// Only events of identifier with this prefix trigger the hook.
+    pub prefix: Option<String>,
+    /// Url or program notifications are delivered to.
+    pub target: Target,
+    /// How many times failed delivery is retried.
+    pub retries: u32,
+}
+
+impl Hook {
+    fn is_triggered(&self, notification: &Notification) -> bool {
+        let triggered = match (&self.trigger, notification) {
+            (Trigger::Event, Notification::Event { .. }) => true,
+            (Trigger::ReceiptThreshold(threshold), Notification::Receipt { receipts, .. }) => {
+                receipts == threshold
+            }
+            (Trigger::RemoteEvent, Notification::RemoteState { .. }) => true,
+            _ => false,
+        };
+        triggered
+            && self
+                .prefix
+                .as_ref()
+                .map_or(true, |prefix| prefix == notification.prefix())
+    }
+
+    // Deliver notification, retrying with growing delay until it succeeds or
+    // retries run out. Each attempt is recorded in delivery log.
+    async fn deliver(&self, notification: Notification, log: Option<PathBuf>) {
+        let payload = notification.to_json();
+        let mut delay = RETRY_DELAY;
+        for attempt in 1..=self.retries + 1 {
+            let result = match &self.target {
+                Target::Url(url) => post(url, &payload).await,
+                Target::Command(command) => run(command, &payload).await,
+            };
+            let delivery = Delivery {
+                timestamp: now(),
+                hook: self.target.to_string(),
+                attempt,
+                delivered: result.is_ok(),
+                error: result.as_ref().err().cloned(),
+                notification: &notification,
+            };
+            if let Some(path) = &log {
+                if let Err(e) = delivery.append(path).await {
+                    warn!(log = %path.display(), "Can't write hook delivery log: {}", e);
+                }
+            }
+            match result {
+                Ok(()) => {
+                    info!(hook = %self.target, kind = notification.kind(), "Hook delivered");
+                    return;
+                }
+                Err(e) => warn!(hook = %self.target, attempt, "Hook failed: {}", e),
+            }
+            if attempt <= self.retries {
+                tokio::time::delay_for(delay).await;
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+            }
+        }
+        warn!(hook = %self.target, kind = notification.kind(), "Hook given up");
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Target::Url(url) => write!(f, "{}", url),
+            Target::Command(command) => write!(f, "{}", command.join(" ")),
+        }
+    }
+}
+
+// Delivery attempt, kept as single line of JSON in delivery log.
+#[derive(Serialize)]
+struct Delivery<'a> {
+    // Unix time in seconds.
+    timestamp: u64,
+    hook: String,
+    attempt: u32,
+    delivered: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    notification: &'a Notification,
+}
+
+impl<'a> Delivery<'a> {
+    async fn append(&self, path: &Path) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(self)?;
+        line.push(b'\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?
+            .write_all(&line)
+            .await
+    }
+}
+
+/// Deliver notifications to hooks they trigger, each delivery on its own
+/// task, so slow hook doesn't hold back the others.
+pub async fn run_hooks(
+    hooks: Vec<Hook>,
+    mut events: broadcast::Receiver<Notification>,
+    log: Option<PathBuf>,
+) {
+    loop {
+        let notification = match events.recv().await {
+            Ok(notification) => notification,
+            Err(broadcast::RecvError::Lagged(missed)) => {
+                warn!(missed, "Hooks lagged behind, notifications dropped");
+                continue;
+            }
+            Err(broadcast::RecvError::Closed) => return,
+        };
+        for hook in hooks.iter().filter(|hook| hook.is_triggered(&notification)) {
+            let hook = hook.clone();
+            let notification = notification.clone();
+            let log = log.clone();
+            tokio::spawn(async move { hook.deliver(notification, log).await });
+        }
+    }
+}
+
+async fn post(url: &str, payload: &str) -> Result<(), String> {
+    match http::post(url, "application/json", payload.as_bytes()).await {
+        Ok((status, _)) if (200..300).contains(&status) => Ok(()),
+        Ok((status, _)) => Err(format!("responded with {}", status)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+async fn run(command: &[String], payload: &str) -> Result<(), String> {
+    let mut child = Command::new(&command[0])
+        .args(&command[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(payload.as_bytes())
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    match child.await {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("exited with {}", status)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,310 @@
+use std::{io, str::from_utf8};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::broadcast,
+};
+
+use futures::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, info, warn};
+
+use crate::{
+    actor::KeriHandle,
+    events::{Filter, Notification},
+    oobi, with_timeout,
+};
+
+// Path of WebSocket endpoint streaming notifications.
+const EVENTS_PATH: &str = "/events";
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+}
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn ok(content_type: &'static str, body: Vec<u8>) -> Self {
+        Response {
+            status: 200,
+            content_type,
+            body,
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Response {
+            status: 404,
+            content_type: "text/plain",
+            body: b"Not Found\n".to_vec(),
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            503 => "Service Unavailable",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+// Serve HTTP endpoints of TDA on given address.
+pub async fn serve(address: String, keri: KeriHandle) -> io::Result<()> {
+    let mut listener = TcpListener::bind(&address).await?;
+    info!("TDA HTTP endpoint listening on: {}", address);
+
+    loop {
+        let (mut socket, _) = listener.accept().await?;
+        let keri = keri.clone();
+        tokio::spawn(async move {
+            // WebSocket handshake is left to tungstenite, so its request is
+            // only peeked at.
+            if let Some(filter) = events_filter(&mut socket).await {
+                let events = keri.call(|keri| keri.events.subscribe()).await;
+                stream_events(socket, events, filter).await;
+                return;
+            }
+            let response = match read_request(&mut socket).await {
+                Some(request) => route(request, keri).await,
+                None => return,
+            };
+            if let Err(e) = write_response(&mut socket, response).await {
+                warn!("Can't write HTTP response: {}", e);
+            }
+        });
+    }
+}
+
+async fn route(request: Request, keri: KeriHandle) -> Response {
+    if request.method != "GET" {
+        return Response {
+            status: 405,
+            content_type: "text/plain",
+            body: b"Method Not Allowed\n".to_vec(),
+        };
+    }
+
+    if request.path == "/health" || request.path == "/ready" {
+        let report = keri.call(|keri| keri.health()).await;
+        let ok = if request.path == "/health" {
+            report.alive()
+        } else {
+            report.ready()
+        };
+        return Response {
+            status: if ok { 200 } else { 503 },
+            content_type: "application/json",
+            body: report.to_json(),
+        };
+    }
+
+    if request.path == "/metrics" {
+        let metrics = keri
+            .call(|keri| {
+                let sns: Vec<_> = keri
+                    .ids
+                    .iter()
+                    .map(|(prefix, id)| (prefix.clone(), id.log.state.sn))
+                    .collect();
+                keri.metrics.render(&sns)
+            })
+            .await;
+        return Response::ok("text/plain; version=0.0.4", metrics.into_bytes());
+    }
+
+    if let Some(oobi) = oobi::Oobi::from_path(&request.path) {
+        // Only KELs of local identifiers are published.
+        if oobi.role != oobi::CONTROLLER_ROLE {
+            return Response::not_found();
+        }
+        let kel = keri
+            .call(move |keri| {
+                keri.ids.get(&oobi.prefix).map(|id| {
+                    id.log
+                        .log
+                        .iter()
+                        .map(|ev| ev.serialize())
+                        .collect::<Result<Vec<_>, _>>()
+                        .map(|events| events.concat())
+                })
+            })
+            .await;
+        return match kel {
+            Some(Ok(kel)) => Response::ok(oobi::KEL_CONTENT_TYPE, kel),
+            Some(Err(e)) => {
+                warn!("Can't serialize KEL: {}", e);
+                Response {
+                    status: 500,
+                    content_type: "text/plain",
+                    body: b"Internal Server Error\n".to_vec(),
+                }
+            }
+            None => Response::not_found(),
+        };
+    }
+
+    Response::not_found()
+}
+
+// Filter given in query of `/events` request, `None` for other requests.
+async fn events_filter(socket: &mut TcpStream) -> Option<Filter> {
+    let mut buf = [0; 1024];
+    let n = socket.peek(&mut buf).await.ok()?;
+    let request = from_utf8(&buf[..n]).ok()?;
+    let mut request_line = request.lines().next()?.split_whitespace();
+    if request_line.next()? != "GET" {
+        return None;
+    }
+    let mut target = request_line.next()?.splitn(2, '?');
+    if target.next()? != EVENTS_PATH {
+        return None;
+    }
+    Some(Filter::from_query(target.next().unwrap_or_default()))
+}
+
+// Send notifications matching the filter as JSON text messages until the
+// subscriber disconnects.
+async fn stream_events(
+    socket: TcpStream,
+    mut events: broadcast::Receiver<Notification>,
+    filter: Filter,
+) {
+    let ws = match tokio_tungstenite::accept_async(socket).await {
+        Ok(ws) => ws,
+        Err(e) => {
+            warn!("WebSocket handshake failed: {}", e);
+            return;
+        }
+    };
+    debug!("Events subscriber connected");
+    let (mut sink, mut stream) = ws.split();
+    loop {
+        tokio::select! {
+            notification = events.recv() => {
+                let message = match notification {
+                    Ok(notification) if filter.matches(&notification) => notification.to_json(),
+                    Ok(_) => continue,
+                    // Subscriber should fetch current state again.
+                    Err(broadcast::RecvError::Lagged(missed)) => {
+                        warn!(missed, "Events subscriber lagged behind");
+                        serde_json::json!({ "kind": "lagged", "missed": missed }).to_string()
+                    }
+                    Err(broadcast::RecvError::Closed) => return,
+                };
+                if sink.send(Message::Text(message)).await.is_err() {
+                    return;
+                }
+            }
+            // Pings are answered by tungstenite, anything else is ignored.
+            message = stream.next() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {
+                    debug!("Events subscriber disconnected");
+                    return;
+                }
+                Some(Ok(_)) => (),
+            },
+        }
+    }
+}
+
+async fn read_request(socket: &mut TcpStream) -> Option<Request> {
+    let mut buf = [0; 1024];
+    let n = socket.read(&mut buf).await.ok()?;
+    let request = from_utf8(&buf[..n]).ok()?;
+
+    // We only care about request line, e.g. `GET /oobi/prefix HTTP/1.1`.
+    let mut request_line = request.lines().next()?.split_whitespace();
+    Some(Request {
+        method: request_line.next()?.to_string(),
+        path: request_line.next()?.to_string(),
+    })
+}
+
+async fn write_response(socket: &mut TcpStream, response: Response) -> io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.reason(),
+        response.content_type,
+        response.body.len()
+    );
+    socket.write_all(head.as_bytes()).await?;
+    socket.write_all(&response.body).await
+}
+
+// Fetch given `http://host:port/path` url. Returns status code and body.
+// Host which doesn't connect or respond in time fails with `TimedOut`.
+pub async fn get(url: &str) -> io::Result<(u16, Vec<u8>)> {
+    request("GET", url, None).await
+}
+
+// Post body of given content type to `http://host:port/path` url. Returns
+// status code and body.
+pub async fn post(url: &str, content_type: &str, body: &[u8]) -> io::Result<(u16, Vec<u8>)> {
+    request("POST", url, Some((content_type, body))).await
+}
+
+async fn request(
+    method: &str,
+    url: &str,
+    body: Option<(&str, &[u8])>,
+) -> io::Result<(u16, Vec<u8>)> {
+    let invalid_url = || io::Error::new(io::ErrorKind::InvalidInput, "Invalid url");
+    if !url.starts_with("http://") {
+        return Err(invalid_url());
+    }
+    let rest = &url["http://".len()..];
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    if host.is_empty() {
+        return Err(invalid_url());
+    }
+
+    let mut stream = with_timeout(TcpStream::connect(host)).await?;
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n",
+        method, path, host
+    );
+    if let Some((content_type, body)) = body {
+        request.push_str(&format!(
+            "Content-Type: {}\r\nContent-Length: {}\r\n",
+            content_type,
+            body.len()
+        ));
+    }
+    request.push_str("\r\n");
+    let mut response = vec![];
+    with_timeout(async {
+        stream.write_all(request.as_bytes()).await?;
+        if let Some((_, body)) = body {
+            stream.write_all(body).await?;
+        }
+        stream.read_to_end(&mut response).await
+    })
+    .await?;
+
+    let invalid_response = || io::Error::new(io::ErrorKind::InvalidData, "Invalid HTTP response");
+    let head_end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(invalid_response)?;
+    let status = from_utf8(&response[..head_end])
+        .map_err(|_| invalid_response())?
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(invalid_response)?;
+
+    Ok((status, response[head_end + 4..].to_vec()))
+}
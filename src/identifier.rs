@@ -0,0 +1,358 @@
+//! Local identifiers: their inception, events and where they are kept.
+
+use keri::{
+    derivation::self_addressing::SelfAddressing,
+    error::Error,
+    event::sections::seal::Seal,
+    event::EventMessage,
+    event_message::SignedEventMessage,
+    prefix::{BasicPrefix, Prefix},
+};
+use tracing::{info, warn};
+
+use crate::{
+    events::{Bus, Notification},
+    keystore::Keystore,
+    log_state::{ExternalKeys, KeyGen, LogState},
+    receipt::Receipt,
+    rotation::{History, Reason},
+    seal,
+    seed::Seed,
+    signer::{Keypair, Signer},
+    storage::Storage,
+    traits::ConfigTrait,
+};
+
+/// Settings from configuration used to incept local identifiers.
+#[derive(Clone)]
+pub struct InceptionConfig {
+    /// Digest derivation used for prefixes and next key commitments.
+    pub derivation: SelfAddressing,
+    /// Witnesses designated in inception event.
+    pub witnesses: Vec<BasicPrefix>,
+    /// Configuration traits declared in inception event.
+    pub traits: Vec<ConfigTrait>,
+    /// Incept transferable identifier, whose keys can be rotated.
+    pub transferable: bool,
+    /// Derive keys from seed instead of generating random ones.
+    pub seed: Option<Seed>,
+    /// Or let external signer generate and hold them.
+    pub signer: Option<Signer>,
+}
+
+impl InceptionConfig {
+    /// Key generation of identifier number `account`.
+    pub fn keygen(&self, account: u32) -> KeyGen {
+        match (&self.seed, &self.signer) {
+            (Some(seed), _) => KeyGen::Seed {
+                seed: seed.clone(),
+                account,
+            },
+            (None, Some(signer)) => KeyGen::External {
+                signer: signer.clone(),
+                account,
+            },
+            (None, None) => KeyGen::Random,
+        }
+    }
+
+    /// Incept identifier number `account`, committing to given next keys or
+    /// newly generated ones.
+    pub fn incept(&self, account: u32, next_keys: Option<ExternalKeys>) -> Result<LogState, Error> {
+        let keygen = self.keygen(account);
+        if self.transferable {
+            LogState::new(
+                self.derivation.clone(),
+                self.witnesses.clone(),
+                next_keys,
+                &self.traits,
+                keygen,
+            )
+        } else if next_keys.is_some() {
+            Err(Error::SemanticError(
+                "non-transferable identifier has no next keys".into(),
+            ))
+        } else {
+            LogState::new_nontransferable(self.derivation.clone(), keygen)
+        }
+    }
+}
+
+/// Local identifier with its keys, KEL, receipts and rotation history, and
+/// where they are kept.
+pub struct Identifier {
+    /// Keys, KEL and receipts of the identifier.
+    pub log: LogState,
+    /// Rotations made and signatures since the last one.
+    pub rotations: History,
+    pub(crate) storage: Option<Storage>,
+    pub(crate) keystore: Option<Keystore>,
+    // Where new events are announced, set by `KeriInstance`.
+    pub(crate) events: Bus,
+}
+
+impl Identifier {
+    // Newly incepted identifier, saved right away.
+    pub(crate) fn incepted(
+        log: LogState,
+        storage: Option<Storage>,
+        keystore: Option<Keystore>,
+    ) -> Result<Self, Error> {
+        let mut rotations = History::default();
+        rotations.record(0, Reason::Inception);
+        Identifier::saved(log, rotations, storage, keystore)
+    }
+
+    // Identifier restored from its KEL with derived or externally held keys,
+    // see `LogState::restore`. Its rotation history is unknown, so age
+    // policy rotates its keys at the first check.
+    pub(crate) fn restored(
+        log: LogState,
+        storage: Option<Storage>,
+        keystore: Option<Keystore>,
+    ) -> Result<Self, Error> {
+        Identifier::saved(log, History::default(), storage, keystore)
+    }
+
+    fn saved(
+        log: LogState,
+        rotations: History,
+        storage: Option<Storage>,
+        keystore: Option<Keystore>,
+    ) -> Result<Self, Error> {
+        let id = Identifier {
+            log,
+            rotations,
+            storage,
+            keystore,
+            events: Bus::default(),
+        };
+        id.persist_keys()?;
+        id.persist()?;
+        Ok(id)
+    }
+
+    // Restore identifier from its storage and keystore. Returns `None` if
+    // nothing was stored yet.
+    pub(crate) fn load(
+        storage: Storage,
+        keystore: Keystore,
+        inception: &InceptionConfig,
+    ) -> Result<Option<Self>, Error> {
+        let stored = match storage.load()? {
+            Some(stored) => stored,
+            None => return Ok(None),
+        };
+        let keys = keystore
+            .load()?
+            .ok_or_else(|| Error::SemanticError("KEL is stored, but keystore is missing".into()))?;
+        let keygen = match keys.account {
+            Some(account) if inception.seed.is_some() || inception.signer.is_some() => {
+                inception.keygen(account)
+            }
+            Some(_) => {
+                return Err(Error::SemanticError(
+                    "keys are derived from seed or held by signer, but neither is configured"
+                        .into(),
+                ))
+            }
+            None => KeyGen::Random,
+        };
+        Ok(Some(Identifier {
+            log: LogState::load(
+                stored,
+                keys.current,
+                keys.next,
+                keys.previous,
+                inception.derivation.clone(),
+                keygen,
+            )?,
+            rotations: storage.load_rotations()?,
+            storage: Some(storage),
+            keystore: Some(keystore),
+            events: Bus::default(),
+        }))
+    }
+
+    /// Prefix of the identifier.
+    pub fn prefix(&self) -> String {
+        self.log.state.prefix.to_str()
+    }
+
+    // Save KEL, receipts and rotation history, if storage is configured.
+    pub(crate) fn persist(&self) -> Result<(), Error> {
+        match &self.storage {
+            Some(storage) => {
+                storage.save(&self.log)?;
+                storage.save_rotations(&self.rotations)
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Save current and next keys, if keystore is configured.
+    pub(crate) fn persist_keys(&self) -> Result<(), Error> {
+        match &self.keystore {
+            Some(keystore) => keystore.save(
+                &self.log.keys,
+                &self.log.next_keys,
+                self.log.keygen.account(),
+            ),
+            None => Ok(()),
+        }
+    }
+
+    // Persist the event just made. If it's establishment event, keys it
+    // replaced stay in keystore until KEL with the event is saved. If it
+    // can't be persisted, it's undone, so that memory doesn't get ahead of
+    // storage.
+    fn commit(&mut self, rotations: History) -> Result<(), Error> {
+        match self.persist_event() {
+            Ok(()) => {
+                self.log.settle();
+                Ok(())
+            }
+            Err(e) => {
+                self.log.undo();
+                self.rotations = rotations;
+                // Keystore may hold keys of the undone event, with the
+                // replaced ones, which `load` would pick anyway.
+                if let Err(e) = self.persist_keys() {
+                    warn!("Can't restore keystore after failed persist: {}", e);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn persist_event(&self) -> Result<(), Error> {
+        match (&self.keystore, self.log.replaced_keys()) {
+            (Some(keystore), Some(replaced)) => {
+                keystore.save_replacing(
+                    &self.log.keys,
+                    &self.log.next_keys,
+                    self.log.keygen.account(),
+                    replaced,
+                )?;
+                self.persist()?;
+                self.persist_keys()
+            }
+            _ => self.persist(),
+        }
+    }
+
+    /// Rotate to next keys, recording why in rotation history.
+    pub fn rotate(&mut self, reason: Reason) -> Result<SignedEventMessage, Error> {
+        self.rotate_with(reason, vec![], None, vec![])
+    }
+
+    /// Rotate with imported next private keys and/or committing to externally
+    /// kept next keys, anchoring given seals, see `LogState::rotate_with`.
+    pub fn rotate_with(
+        &mut self,
+        reason: Reason,
+        imported: Vec<Keypair>,
+        next: Option<ExternalKeys>,
+        seals: Vec<Seal>,
+    ) -> Result<SignedEventMessage, Error> {
+        let rot = self.log.rotate_with(imported, next, seals)?;
+        let rotations = self.rotations.clone();
+        self.rotations.record(rot.event_message.event.sn, reason);
+        self.commit(rotations)?;
+        self.events.publish(Notification::event(&rot));
+        Ok(rot)
+    }
+
+    /// Rotate committing to empty next key set, see `LogState::abandon`.
+    pub fn abandon(&mut self, imported: Vec<Keypair>) -> Result<SignedEventMessage, Error> {
+        let rot = self.log.abandon(imported)?;
+        let rotations = self.rotations.clone();
+        self.rotations
+            .record(rot.event_message.event.sn, Reason::Abandonment);
+        self.commit(rotations)?;
+        info!(sn = rot.event_message.event.sn, "Identifier abandoned");
+        self.events.publish(Notification::event(&rot));
+        Ok(rot)
+    }
+
+    /// Recovery rotation at `sn`, superseding interaction events made with
+    /// compromised keys, see `LogState::recover`.
+    pub fn recover(
+        &mut self,
+        sn: u64,
+        imported: Vec<Keypair>,
+        next: Option<ExternalKeys>,
+    ) -> Result<SignedEventMessage, Error> {
+        let before = self.log.superseded.len();
+        let rot = self.log.recover(sn, imported, next)?;
+        let superseded = self.log.superseded.len() - before;
+        let rotations = self.rotations.clone();
+        self.rotations.record(sn, Reason::Recovery);
+        self.commit(rotations)?;
+        warn!(
+            sn,
+            superseded, "Recovery rotation superseded interaction events"
+        );
+        self.events.publish(Notification::event(&rot));
+        Ok(rot)
+    }
+
+    /// Make interaction event anchoring digest of given payload.
+    pub fn make_ixn(&mut self, payload: &str) -> Result<SignedEventMessage, Error> {
+        let ixn = self.log.make_ixn(payload)?;
+        let rotations = self.rotations.clone();
+        self.rotations.signed();
+        self.commit(rotations)?;
+        self.events.publish(Notification::event(&ixn));
+        Ok(ixn)
+    }
+
+    /// Make interaction event anchoring given seals.
+    pub fn anchor(&mut self, seals: Vec<Seal>) -> Result<SignedEventMessage, Error> {
+        let ixn = self.log.anchor(seals)?;
+        let rotations = self.rotations.clone();
+        self.rotations.signed();
+        self.commit(rotations)?;
+        self.events.publish(Notification::event(&ixn));
+        Ok(ixn)
+    }
+
+    /// Parse seal arguments of `IXN` and `ROT` commands, see `seal::parse`.
+    pub fn parse_seals(&self, args: &[&str]) -> Result<Vec<Seal>, Error> {
+        args.iter()
+            .map(|arg| seal::parse(arg, &self.log.derivation))
+            .collect()
+    }
+
+    /// Receipt given event of other identifier.
+    pub fn make_rct(&mut self, event: EventMessage) -> Result<Receipt, Error> {
+        let rct = self.log.make_rct(event)?;
+        self.rotations.signed();
+        Ok(rct)
+    }
+
+    /// Storage directory is accessible, or storage isn't configured.
+    pub fn storage_available(&self) -> bool {
+        self.storage
+            .as_ref()
+            .map(|storage| storage.is_available())
+            .unwrap_or(true)
+    }
+
+    /// Keys can be loaded and used for signing: keystore exists and external
+    /// signer holding them, if any, is reachable.
+    pub fn keystore_unlocked(&self) -> bool {
+        let keystore = self
+            .keystore
+            .as_ref()
+            .map(|keystore| keystore.is_available())
+            .unwrap_or(true);
+        let signer = self
+            .log
+            .keygen
+            .signer()
+            .map(|signer| signer.is_available())
+            .unwrap_or(true);
+        keystore && signer
+    }
+}
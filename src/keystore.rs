@@ -0,0 +1,307 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use keri::error::Error;
+use serde::{Deserialize, Serialize};
+use ursa::keys::PublicKey;
+use zeroize::Zeroize;
+
+use crate::{
+    log_state::{ExternalKeys, NextKeys, SigningKey},
+    secret::Secret,
+    signer::Keypair,
+    storage,
+};
+
+fn encode(key: &[u8]) -> String {
+    base64::encode_config(key, base64::URL_SAFE)
+}
+
+fn decode(key: &str) -> Result<Vec<u8>, Error> {
+    base64::decode_config(key, base64::URL_SAFE)
+        .map_err(|e| Error::SemanticError(format!("Invalid key encoding: {}", e)))
+}
+
+// Private key is missing if it's held by external signer.
+#[derive(Serialize, Deserialize)]
+struct StoredKeypair {
+    public: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    private: Option<String>,
+}
+
+impl StoredKeypair {
+    fn new(keypair: &Keypair) -> Self {
+        StoredKeypair {
+            public: encode(&keypair.public.0),
+            private: keypair
+                .private
+                .as_ref()
+                .map(|private| encode(private.expose())),
+        }
+    }
+
+    fn keypair(&self) -> Result<Keypair, Error> {
+        Ok(Keypair {
+            public: PublicKey(decode(&self.public)?),
+            private: match &self.private {
+                Some(private) => Some(Secret::new(decode(private)?)),
+                None => None,
+            },
+        })
+    }
+
+    // Keypair with private key, as needed for import.
+    fn local(&self) -> Result<Keypair, Error> {
+        match &self.private {
+            Some(_) => self.keypair(),
+            None => Err(Error::SemanticError(format!(
+                "Missing private key of {}",
+                self.public
+            ))),
+        }
+    }
+}
+
+// Encoded private key is wiped too, once it's written or decoded.
+impl Drop for StoredKeypair {
+    fn drop(&mut self) {
+        if let Some(private) = &mut self.private {
+            private.zeroize();
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredSigningKey {
+    index: u16,
+    #[serde(flatten)]
+    keypair: StoredKeypair,
+}
+
+// Next public keys kept outside of TDA, as exported from the device which
+// holds their private keys.
+#[derive(Serialize, Deserialize)]
+struct StoredExternalKeys {
+    threshold: u64,
+    public_keys: Vec<String>,
+}
+
+impl StoredExternalKeys {
+    fn new(keys: &ExternalKeys) -> Self {
+        StoredExternalKeys {
+            threshold: keys.threshold,
+            public_keys: keys.public_keys.iter().map(|pk| encode(&pk.0)).collect(),
+        }
+    }
+
+    fn keys(&self) -> Result<ExternalKeys, Error> {
+        let public_keys = self
+            .public_keys
+            .iter()
+            .map(|pk| decode(pk).map(PublicKey))
+            .collect::<Result<Vec<_>, _>>()?;
+        if self.threshold == 0 || self.threshold > public_keys.len() as u64 {
+            return Err(Error::SemanticError(format!(
+                "Invalid threshold {} for {} next keys",
+                self.threshold,
+                public_keys.len()
+            )));
+        }
+        Ok(ExternalKeys {
+            threshold: self.threshold,
+            public_keys,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum StoredNextKeys {
+    Local(StoredKeypair),
+    External(StoredExternalKeys),
+    Empty,
+}
+
+impl StoredNextKeys {
+    fn new(next_keys: &NextKeys) -> Self {
+        match next_keys {
+            NextKeys::Local(keypair) => StoredNextKeys::Local(StoredKeypair::new(keypair)),
+            NextKeys::External(keys) => StoredNextKeys::External(StoredExternalKeys::new(keys)),
+            NextKeys::Empty => StoredNextKeys::Empty,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredKeys {
+    current: Vec<StoredSigningKey>,
+    next: StoredNextKeys,
+    // Account of keys derived from seed or held by external signer, absent
+    // for random ones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    account: Option<u32>,
+    // Keys replaced by establishment event, kept until KEL with the event is
+    // saved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    previous: Option<Box<StoredKeys>>,
+}
+
+impl StoredKeys {
+    fn new(keys: &[SigningKey], next_keys: &NextKeys, account: Option<u32>) -> Self {
+        StoredKeys {
+            current: keys
+                .iter()
+                .map(|key| StoredSigningKey {
+                    index: key.index,
+                    keypair: StoredKeypair::new(&key.keypair),
+                })
+                .collect(),
+            next: StoredNextKeys::new(next_keys),
+            account,
+            previous: None,
+        }
+    }
+
+    fn keys(&self) -> Result<(Vec<SigningKey>, NextKeys), Error> {
+        let keys = self
+            .current
+            .iter()
+            .map(|key| {
+                Ok(SigningKey {
+                    index: key.index,
+                    keypair: key.keypair.keypair()?,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let next_keys = match &self.next {
+            StoredNextKeys::Local(keypair) => NextKeys::Local(keypair.keypair()?),
+            StoredNextKeys::External(keys) => NextKeys::External(keys.keys()?),
+            StoredNextKeys::Empty => NextKeys::Empty,
+        };
+        Ok((keys, next_keys))
+    }
+}
+
+// Keys read from keystore.
+pub struct Keys {
+    pub current: Vec<SigningKey>,
+    pub next: NextKeys,
+    // Account of keys derived from seed or held by external signer.
+    pub account: Option<u32>,
+    // Current and next keys replaced by establishment event, which may be
+    // missing in stored KEL, see `Keystore::save_replacing`.
+    pub previous: Option<(Vec<SigningKey>, NextKeys)>,
+}
+
+// File holding current signing keys and next keys of the identifier.
+#[derive(Clone)]
+pub struct Keystore {
+    path: PathBuf,
+}
+
+impl Keystore {
+    pub fn new(path: PathBuf) -> Self {
+        Keystore { path }
+    }
+
+    pub fn is_available(&self) -> bool {
+        fs::metadata(&self.path).is_ok()
+    }
+
+    pub fn save(
+        &self,
+        keys: &[SigningKey],
+        next_keys: &NextKeys,
+        account: Option<u32>,
+    ) -> Result<(), Error> {
+        self.write(&StoredKeys::new(keys, next_keys, account))
+    }
+
+    // Save keys made by establishment event together with the ones it
+    // replaced, so that keystore matches stored KEL whether the event is
+    // saved in it or not. Once it is, keys are saved again without the
+    // replaced ones. Replaced next keys are `None` if they were kept locally
+    // and became current key, see `LogState::replaced_keys`.
+    pub fn save_replacing(
+        &self,
+        keys: &[SigningKey],
+        next_keys: &NextKeys,
+        account: Option<u32>,
+        replaced: (&[SigningKey], Option<&NextKeys>),
+    ) -> Result<(), Error> {
+        let (replaced_keys, replaced_next) = replaced;
+        let mut previous = StoredKeys::new(replaced_keys, &NextKeys::Empty, account);
+        previous.next = match replaced_next {
+            Some(next_keys) => StoredNextKeys::new(next_keys),
+            None => StoredNextKeys::Local(StoredKeypair::new(&keys[0].keypair)),
+        };
+        let mut stored = StoredKeys::new(keys, next_keys, account);
+        stored.previous = Some(Box::new(previous));
+        self.write(&stored)
+    }
+
+    fn write(&self, keys: &StoredKeys) -> Result<(), Error> {
+        let mut content = serde_json::to_vec_pretty(keys)
+            .map_err(|e| Error::SemanticError(format!("Can't serialize keys: {}", e)))?;
+        let written = storage::write_atomic(&self.path, &content, true).map_err(|e| {
+            Error::SemanticError(format!("Can't write {}: {}", self.path.display(), e))
+        });
+        content.zeroize();
+        written
+    }
+
+    // Returns `None` if keystore doesn't exist yet.
+    pub fn load(&self) -> Result<Option<Keys>, Error> {
+        let mut content = match fs::read(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(Error::SemanticError(format!(
+                    "Can't read {}: {}",
+                    self.path.display(),
+                    e
+                )))
+            }
+        };
+        let stored: Result<StoredKeys, _> = serde_json::from_slice(&content);
+        content.zeroize();
+        let stored =
+            stored.map_err(|e| Error::SemanticError(format!("Can't parse keystore: {}", e)))?;
+        let (current, next) = stored.keys()?;
+        let previous = match &stored.previous {
+            Some(previous) => Some(previous.keys()?),
+            None => None,
+        };
+        Ok(Some(Keys {
+            current,
+            next,
+            account: stored.account,
+            previous,
+        }))
+    }
+}
+
+// Read next public keys from JSON file:
+// `{"threshold": 1, "public_keys": ["<base64url>", ...]}`.
+pub fn read_external_keys(path: &Path) -> Result<ExternalKeys, Error> {
+    let content = fs::read(path)
+        .map_err(|e| Error::SemanticError(format!("Can't read {}: {}", path.display(), e)))?;
+    let stored: StoredExternalKeys = serde_json::from_slice(&content)
+        .map_err(|e| Error::SemanticError(format!("Can't parse {}: {}", path.display(), e)))?;
+    stored.keys()
+}
+
+// Read keypairs imported for rotation from JSON file:
+// `[{"public": "<base64url>", "private": "<base64url>"}, ...]`.
+pub fn read_keypairs(path: &Path) -> Result<Vec<Keypair>, Error> {
+    let mut content = fs::read(path)
+        .map_err(|e| Error::SemanticError(format!("Can't read {}: {}", path.display(), e)))?;
+    let stored: Result<Vec<StoredKeypair>, _> = serde_json::from_slice(&content);
+    content.zeroize();
+    let stored = stored
+        .map_err(|e| Error::SemanticError(format!("Can't parse {}: {}", path.display(), e)))?;
+    stored.iter().map(StoredKeypair::local).collect()
+}
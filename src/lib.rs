@@ -0,0 +1,873 @@
+//! Trusted Digital Assistant, standalone version: a KERI controller which
+//! keeps key event logs (KELs) of its local identifiers, rotates their keys
+//! and exchanges events and receipts with other TDAs over TCP.
+//!
+//! [`run`] serves everything a [`config::Config`] describes. To embed TDA or
+//! drive it from tests, make a [`KeriInstance`] with
+//! [`KeriInstance::from_config`], move it to its own thread with
+//! [`actor::KeriHandle::spawn`] and pass events and commands to
+//! [`control::execute`] or serve connections with
+//! [`control::handle_connection`]. Events are sent to other TDAs with
+//! [`send_event`] and [`send_receipt`].
+
+#![warn(missing_docs)]
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    io,
+    str::from_utf8,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+use keri::{
+    error::Error as KeriError, event::event_data::EventData, event_message::parse,
+    event_message::SignedEventMessage, prefix::Prefix, state::IdentifierState,
+};
+
+pub mod actor;
+pub mod config;
+pub mod control;
+pub mod events;
+pub mod health;
+pub mod hooks;
+mod http;
+pub mod identifier;
+mod keystore;
+pub mod log_state;
+pub mod logging;
+pub mod metrics;
+mod oobi;
+#[cfg(feature = "pkcs11")]
+mod pkcs11;
+pub mod receipt;
+mod remote;
+pub mod rotation;
+mod seal;
+mod secret;
+pub mod seed;
+pub mod signer;
+mod storage;
+pub mod traits;
+
+pub use secret::lock_memory;
+
+// How long to wait for other host to accept connection or to respond.
+const PEER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Primary identifier to restore from its KEL instead of loading or incepting
+/// it, with keys derived from the seed or held by external signer.
+pub struct Recovery {
+    /// KEL of the primary identifier.
+    pub kel: Vec<SignedEventMessage>,
+    /// Account of the next identifier incepted with `NEW`. Accounts before it
+    /// may back identifiers made on the lost machine, so their keys are never
+    /// derived again.
+    pub next_account: u32,
+}
+
+/// Local identifiers of TDA with what it knows about other identifiers. It's
+/// owned by its own thread, see `actor::KeriHandle`, and driven by
+/// `control::execute` for events and commands received over connections, and
+/// by `run` which serves them.
+pub struct KeriInstance {
+    // Local identifiers, indexed by prefix.
+    ids: BTreeMap<String, identifier::Identifier>,
+    // Identifier loaded or incepted from configuration. It can't be deleted
+    // and control commands act on it until other identifier is selected.
+    primary: String,
+    inception: identifier::InceptionConfig,
+    // Account of the next incepted identifier. It only grows, so keys of
+    // deleted identifiers are never derived again.
+    next_account: u32,
+    storage: Option<storage::Storage>,
+    // KELs of other identifiers, indexed by prefix.
+    remote_ids: HashMap<String, remote::RemoteLog>,
+    // Respond to events of other identifiers with receipts.
+    auto_receipt: bool,
+    metrics: Arc<metrics::Metrics>,
+    // Changes of KELs and receipts, streamed by `/events` endpoint.
+    events: events::Bus,
+    // Set once TCP listener is bound.
+    listening: bool,
+}
+
+impl KeriInstance {
+    /// Load identifiers from configured storage or incept a new one, or
+    /// restore primary identifier as `recovery` describes.
+    pub fn from_config(
+        config: &config::Config,
+        recovery: Option<Recovery>,
+        metrics: Arc<metrics::Metrics>,
+    ) -> Result<Self, KeriError> {
+        let config_error = |e: config::ConfigError| KeriError::SemanticError(e.to_string());
+        let inception = identifier::InceptionConfig {
+            derivation: config.digest().map_err(config_error)?,
+            witnesses: config.witnesses().map_err(config_error)?,
+            traits: config.traits().map_err(config_error)?,
+            transferable: config.identifier.transferable,
+            seed: match &config.identifier.mnemonic_file {
+                Some(path) => Some(seed::Seed::from_file(path)?),
+                None => None,
+            },
+            signer: config.signer().map_err(config_error)?,
+        };
+        let storage = match &config.storage.path {
+            Some(path) => Some(storage::Storage::new(path.clone()).map_err(|e| {
+                KeriError::SemanticError(format!("Can't open storage {}: {}", path.display(), e))
+            })?),
+            None => None,
+        };
+        let keystore = config.keystore_path().map(keystore::Keystore::new);
+
+        let loaded = match (&storage, &keystore) {
+            (Some(storage), Some(keystore)) => {
+                identifier::Identifier::load(storage.clone(), keystore.clone(), &inception)?
+            }
+            _ => None,
+        };
+        let recovered_account = recovery
+            .as_ref()
+            .map(|recovery| recovery.next_account.max(1));
+        let primary = match (loaded, recovery) {
+            (Some(_), Some(_)) => {
+                return Err(KeriError::SemanticError(
+                    "storage already holds an identifier, refusing to recover over it".into(),
+                ))
+            }
+            (Some(id), None) => id,
+            (None, Some(recovery)) => {
+                let log = log_state::LogState::restore(
+                    recovery.kel,
+                    inception.derivation.clone(),
+                    inception.keygen(0),
+                )?;
+                info!(sn = log.state.sn, "Identifier recovered from seed");
+                identifier::Identifier::restored(log, storage.clone(), keystore)?
+            }
+            (None, None) => {
+                let next_keys = match &config.identifier.next_keys {
+                    Some(path) => Some(keystore::read_external_keys(path)?),
+                    None => None,
+                };
+                identifier::Identifier::incepted(
+                    inception.incept(0, next_keys)?,
+                    storage.clone(),
+                    keystore,
+                )?
+            }
+        };
+
+        let mut ids = BTreeMap::new();
+        if let Some(storage) = &storage {
+            let prefixes = storage
+                .identifiers()
+                .map_err(|e| KeriError::SemanticError(format!("Can't list identifiers: {}", e)))?;
+            for prefix in prefixes {
+                let id_storage = storage.identifier(&prefix).map_err(|e| {
+                    KeriError::SemanticError(format!("Can't open storage of {}: {}", prefix, e))
+                })?;
+                let id_keystore = keystore::Keystore::new(id_storage.keystore_path());
+                match identifier::Identifier::load(id_storage, id_keystore, &inception)? {
+                    Some(id) if id.prefix() == prefix => {
+                        ids.insert(prefix, id);
+                    }
+                    _ => warn!(prefix = %prefix, "Skipping storage of unknown identifier"),
+                }
+            }
+        }
+        let primary_prefix = primary.prefix();
+        ids.insert(primary_prefix.clone(), primary);
+        let stored_account = match &storage {
+            Some(storage) => storage.load_next_account()?,
+            None => None,
+        };
+        let next_account = ids
+            .values()
+            .filter_map(|id| id.log.keygen.account())
+            .map(|account| account + 1)
+            .chain(stored_account)
+            .chain(recovered_account)
+            .max()
+            .unwrap_or(0);
+        if let (Some(storage), Some(_)) = (&storage, recovered_account) {
+            storage.save_next_account(next_account)?;
+        }
+        let events = events::Bus::default();
+        for id in ids.values_mut() {
+            id.events = events.clone();
+        }
+
+        Ok(KeriInstance {
+            ids,
+            primary: primary_prefix,
+            inception,
+            next_account,
+            storage,
+            remote_ids: HashMap::new(),
+            auto_receipt: config.receipts.auto_receipt,
+            metrics,
+            events,
+            listening: false,
+        })
+    }
+
+    /// Prefix of identifier loaded or incepted from configuration.
+    pub fn primary(&self) -> &str {
+        &self.primary
+    }
+
+    /// Changes of KELs and receipts of this instance, see `events::Notification`.
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<events::Notification> {
+        self.events.subscribe()
+    }
+
+    /// Local identifier with given prefix.
+    pub fn id(&self, prefix: &str) -> Result<&identifier::Identifier, KeriError> {
+        self.ids.get(prefix).ok_or_else(|| {
+            KeriError::SemanticError(format!("unknown local identifier: {}", prefix))
+        })
+    }
+
+    /// Local identifier with given prefix, to make events with.
+    pub fn id_mut(&mut self, prefix: &str) -> Result<&mut identifier::Identifier, KeriError> {
+        self.ids.get_mut(prefix).ok_or_else(|| {
+            KeriError::SemanticError(format!("unknown local identifier: {}", prefix))
+        })
+    }
+
+    /// Incept additional local identifier, kept in its own directory of the
+    /// storage. Returns its prefix.
+    pub fn incept(&mut self, next: Option<log_state::ExternalKeys>) -> Result<String, KeriError> {
+        // Seeded identifiers are numbered in order of inception. The number
+        // is taken before inception, so it's not reused even if inception
+        // fails halfway.
+        let account = self.next_account;
+        self.next_account += 1;
+        if let Some(storage) = &self.storage {
+            storage.save_next_account(self.next_account)?;
+        }
+        let log = self.inception.incept(account, next)?;
+        let prefix = log.state.prefix.to_str();
+        if self.ids.contains_key(&prefix) {
+            return Err(KeriError::SemanticError(format!(
+                "identifier {} already exists",
+                prefix
+            )));
+        }
+        let storage = match &self.storage {
+            Some(storage) => Some(storage.identifier(&prefix).map_err(|e| {
+                KeriError::SemanticError(format!("Can't open storage of {}: {}", prefix, e))
+            })?),
+            None => None,
+        };
+        let keystore = storage
+            .as_ref()
+            .map(|storage| keystore::Keystore::new(storage.keystore_path()));
+        let mut id = identifier::Identifier::incepted(log, storage, keystore)?;
+        info!(prefix = %prefix, "Incepted local identifier");
+        id.events = self.events.clone();
+        if let Some(icp) = id.log.log.first() {
+            self.events.publish(events::Notification::event(icp));
+        }
+        self.ids.insert(prefix.clone(), id);
+        Ok(prefix)
+    }
+
+    /// Forget local identifier with its keys, KEL and receipts.
+    pub fn delete(&mut self, prefix: &str) -> Result<(), KeriError> {
+        if prefix == self.primary {
+            return Err(KeriError::SemanticError(
+                "primary identifier can't be deleted".into(),
+            ));
+        }
+        let id = self.id(prefix)?;
+        // Identifier stays known until its storage is gone, so it doesn't
+        // come back after restart if removal fails.
+        if let Some(storage) = &id.storage {
+            storage.remove().map_err(|e| {
+                KeriError::SemanticError(format!("Can't remove storage of {}: {}", prefix, e))
+            })?;
+        }
+        self.ids.remove(prefix);
+        info!(prefix = %prefix, "Deleted local identifier");
+        Ok(())
+    }
+
+    // Verify transferable receipt and add it to sigs_map of the local
+    // identifier whose event it receipts.
+    fn add_sig(
+        &mut self,
+        validator: &IdentifierState,
+        sigs: SignedEventMessage,
+    ) -> Result<(), KeriError> {
+        let prefix = sigs.event_message.event.prefix.to_str();
+        let metrics = Arc::clone(&self.metrics);
+        let id = match self.id_mut(&prefix) {
+            Ok(id) => id,
+            Err(e) => {
+                metrics.verification_failed();
+                return Err(e);
+            }
+        };
+        let sn = sigs.event_message.event.sn;
+        let escrowed = id.log.escrow_sigs.len();
+        let notification = events::Notification::receipt(validator, &sigs);
+        if let Err(e) = id.log.add_sig(validator, sigs) {
+            metrics.verification_failed();
+            return Err(e);
+        }
+        let notification = if id.log.escrow_sigs.len() > escrowed {
+            metrics.receipt_escrowed();
+            notification.escrowed()
+        } else {
+            metrics.receipt_stored();
+            notification.counted(id.log.receipts(sn))
+        };
+        id.persist()?;
+        id.events.publish(notification);
+        Ok(())
+    }
+
+    /// Health report served on `/health` and `/ready` and by `HLT`.
+    pub fn health(&self) -> health::Report {
+        let ids = self.ids.values();
+        health::Report {
+            listening: self.listening,
+            storage_available: ids.clone().all(|id| id.storage_available()),
+            keystore_unlocked: ids.clone().all(|id| id.keystore_unlocked()),
+            escrowed_receipts: ids.clone().map(|id| id.log.escrow_sigs.len()).sum(),
+            kel_verified: ids.clone().all(|id| id.log.verify_kel()),
+            sn: self.ids[&self.primary].log.state.sn,
+        }
+    }
+
+    /// Get known state of given identifier or default if we never saw it.
+    pub fn remote_state(&self, prefix: &str) -> IdentifierState {
+        self.remote_ids
+            .get(prefix)
+            .map(|log| log.state.clone())
+            .unwrap_or_default()
+    }
+
+    // Verify event of other identifier and update its KEL.
+    fn apply_remote(&mut self, msg: &SignedEventMessage) -> Result<IdentifierState, KeriError> {
+        if let Err(e) = self.check_delegator(msg) {
+            self.metrics.verification_failed();
+            return Err(e);
+        }
+        let prefix = msg.event_message.event.prefix.to_str();
+        let mut log = self.remote_ids.get(&prefix).cloned().unwrap_or_default();
+        let superseded = log.apply(msg).map_err(|e| {
+            self.metrics.verification_failed();
+            e
+        })?;
+        if log.is_abandoned() {
+            info!(event = %logging::EventSummary(msg), "Identifier abandoned");
+        }
+        if superseded > 0 {
+            warn!(
+                event = %logging::EventSummary(msg),
+                superseded,
+                "Recovery rotation superseded interaction events"
+            );
+        }
+        let state = log.state.clone();
+        self.remote_ids.insert(prefix, log);
+        self.events
+            .publish(events::Notification::remote_state(&state));
+        self.process_escrow(&state);
+        Ok(state)
+    }
+
+    // Verify receipts escrowed until given validator's state was known.
+    fn process_escrow(&mut self, validator: &IdentifierState) {
+        for id in self.ids.values_mut() {
+            let added = id.log.process_escrow(validator);
+            if added.is_empty() {
+                continue;
+            }
+            info!(
+                validator = %validator.prefix.to_str(),
+                count = added.len(),
+                "Escrowed receipts verified"
+            );
+            for sn in added {
+                self.metrics.receipt_stored();
+                id.events.publish(events::Notification::Receipt {
+                    prefix: id.prefix(),
+                    sn,
+                    validators: vec![validator.prefix.to_str()],
+                    receipts: id.log.receipts(sn),
+                });
+            }
+            if let Err(e) = id.persist() {
+                error!("Can't persist receipts: {}", e);
+            }
+        }
+    }
+
+    // Reject delegated event if its delegator declared do-not-delegate trait.
+    fn check_delegator(&self, msg: &SignedEventMessage) -> Result<(), KeriError> {
+        let delegator = match traits::delegator(msg) {
+            Some(delegator) => delegator.to_str(),
+            None => return Ok(()),
+        };
+        let delegator_traits = if let Some(id) = self.ids.get(&delegator) {
+            id.log.traits()
+        } else {
+            self.remote_ids
+                .get(&delegator)
+                .map(|log| log.traits())
+                .unwrap_or_default()
+        };
+        if delegator_traits.contains(&traits::ConfigTrait::DoNotDelegate) {
+            return Err(KeriError::SemanticError(format!(
+                "delegator {} can't delegate",
+                delegator
+            )));
+        }
+        Ok(())
+    }
+
+    /// Verify whole KEL of given identifier from inception and store its state.
+    /// KEL of already known identifier is only accepted if it extends the
+    /// known one.
+    pub fn process_kel(
+        &mut self,
+        prefix: &str,
+        kel: &[SignedEventMessage],
+    ) -> Result<IdentifierState, KeriError> {
+        let resolved = remote::RemoteLog::from_kel(kel)?;
+        if resolved.state.prefix.to_str() != prefix {
+            return Err(KeriError::SemanticError(
+                "KEL doesn't match identifier prefix".into(),
+            ));
+        }
+        let log = match self.remote_ids.get(prefix) {
+            Some(known) => known.extended_by(&resolved)?,
+            None => resolved,
+        };
+        let state = log.state.clone();
+        self.remote_ids.insert(prefix.to_string(), log);
+        // Not an accepted event, so it doesn't trigger `remote_event` hooks.
+        self.events.publish(events::Notification::resolved(&state));
+        self.process_escrow(&state);
+        Ok(state)
+    }
+
+    // Verify non-transferable receipt of our event and add it to sigs_map of
+    // the local identifier whose event it receipts.
+    fn add_nontransferable_sig(&mut self, rct: receipt::NontransferableReceipt) {
+        self.metrics.event_received("Rct");
+        let sn = rct.body.event.sn;
+        let prefix = rct.body.event.prefix.to_str();
+        let metrics = Arc::clone(&self.metrics);
+        let notification = events::Notification::nontransferable_receipt(&rct);
+        let added = self
+            .id_mut(&prefix)
+            .and_then(|id| id.log.add_nontransferable_sig(rct).map(|_| id));
+        match added {
+            Ok(id) => {
+                metrics.receipt_stored();
+                id.events.publish(notification.counted(id.log.receipts(sn)));
+                info!(sn, "Got non-transferable receipt");
+                if let Err(e) = id.persist() {
+                    error!("Can't persist receipt: {}", e);
+                }
+            }
+            Err(e) => {
+                metrics.verification_failed();
+                warn!(sn, "Can't verify non-transferable receipt: {}", e);
+            }
+        }
+    }
+
+    /// Process event received from other TDA. Events of other identifiers are
+    /// receipted by `selected` local identifier.
+    pub fn parse_event(&mut self, selected: &str, event: &str) -> Vec<u8> {
+        let mut response: Vec<u8> = vec![];
+
+        // Non-transferable receipts are not understood by keri parser.
+        if let Some((_, rct)) = receipt::parse_nontransferable(event) {
+            self.add_nontransferable_sig(rct);
+            return response;
+        }
+
+        // Deserialize signed msg
+        let msg = match parse::signed_message(event) {
+            Ok((_, msg)) => msg,
+            Err(_) => {
+                warn!("Can't parse event message");
+                return response;
+            }
+        };
+        let m = msg.clone();
+
+        // Connection span carries prefix of the identifier we talk to.
+        tracing::Span::current().record("prefix", &m.event_message.event.prefix.to_str().as_str());
+        debug!(event = %logging::EventSummary(&m), "Process keri event");
+        self.metrics.event_received(logging::event_type(&m));
+
+        // Process message.
+        response = match msg.event_message.event.event_data {
+            // if it's receipt message, verify it and add to sigs_map.
+            EventData::Vrc(ref rct) => {
+                debug!("Receipt message, verifying");
+                let validator = self.remote_state(&rct.validator_location_seal.prefix.to_str());
+                match self.add_sig(&validator, msg) {
+                    Ok(()) => info!(sn = m.event_message.event.sn, "Got receipt"),
+                    Err(e) => {
+                        warn!(event = %logging::EventSummary(&m), "Can't verify receipt: {}", e)
+                    }
+                }
+                vec![]
+            }
+            // if it's inception event respond with last establishment message and receipt message.
+            EventData::Icp(_) => {
+                if let Err(e) = self.apply_remote(&msg) {
+                    warn!(event = %logging::EventSummary(&m), "Can't verify received message: {}", e);
+                    return vec![];
+                }
+
+                let respond = match self.respond_to_inception(selected, msg) {
+                    Ok(respond) => respond,
+                    Err(e) => {
+                        warn!("Can't respond to inception: {}", e);
+                        return vec![];
+                    }
+                };
+                info!(event = %logging::EventSummary(&m), "Got inception event");
+                respond
+            }
+            // if it's rotation event, respond with receipt event.
+            EventData::Rot(_) | EventData::Ixn(_) => {
+                if let Err(e) = self.apply_remote(&msg) {
+                    warn!(event = %logging::EventSummary(&m), "Can't verify received message: {}", e);
+                    return vec![];
+                }
+                let respond = if self.auto_receipt {
+                    match self
+                        .id_mut(selected)
+                        .and_then(|id| id.make_rct(msg.event_message))
+                        .and_then(|receipt| receipt.serialize())
+                    {
+                        Ok(receipt) => receipt,
+                        Err(e) => {
+                            warn!("Can't make a receipt: {}", e);
+                            vec![]
+                        }
+                    }
+                } else {
+                    vec![]
+                };
+                info!(event = %logging::EventSummary(&m), "Got event");
+                respond
+            }
+            _ => response,
+        };
+        response
+    }
+
+    // Respond to inception event of other identifier with last event of
+    // `selected` local identifier and, if receipts are made automatically,
+    // its receipt of the inception.
+    fn respond_to_inception(
+        &mut self,
+        selected: &str,
+        icp: SignedEventMessage,
+    ) -> Result<Vec<u8>, KeriError> {
+        let auto_receipt = self.auto_receipt;
+        let id = self.id_mut(selected)?;
+        let last_event = id
+            .log
+            .log
+            .last()
+            .ok_or_else(|| KeriError::SemanticError("local KEL is empty".into()))?;
+        let mut respond = last_event.serialize()?;
+        if auto_receipt {
+            match id.make_rct(icp.event_message) {
+                Ok(receipt) => respond.extend(receipt.serialize()?),
+                Err(e) => warn!("Can't make a receipt: {}", e),
+            }
+        }
+        Ok(respond)
+    }
+
+    /// Handle events which other TDA sent in response to our event: verify
+    /// receipts and add them to sigs_map, apply other events to state of
+    /// their identifier. Returns receipts of those events made by `selected`
+    /// local identifier, which should be sent back to responder.
+    pub fn process_response(
+        &mut self,
+        selected: &str,
+        address: &str,
+        response: Vec<SignedEventMessage>,
+        nontransferable: Vec<receipt::NontransferableReceipt>,
+    ) -> Vec<receipt::Receipt> {
+        for rct in nontransferable {
+            self.add_nontransferable_sig(rct);
+        }
+        let mut receipts = vec![];
+        for sig_msg in response {
+            self.metrics.event_received(logging::event_type(&sig_msg));
+            match sig_msg.event_message.event.event_data {
+                EventData::Vrc(ref rct) => {
+                    let validator = self.remote_state(&rct.validator_location_seal.prefix.to_str());
+                    match self.add_sig(&validator, sig_msg) {
+                        Ok(()) => info!(peer = %address, "Got receipt"),
+                        Err(e) => warn!(peer = %address, "Can't verify receipt: {}", e),
+                    }
+                }
+                _ => {
+                    info!(
+                        peer = %address,
+                        event = %logging::EventSummary(&sig_msg),
+                        "Got event"
+                    );
+                    if let Err(e) = self.apply_remote(&sig_msg) {
+                        warn!(peer = %address, "Can't verify message from response: {}", e);
+                        continue;
+                    }
+                    if self.auto_receipt {
+                        match self
+                            .id_mut(selected)
+                            .and_then(|id| id.make_rct(sig_msg.event_message))
+                        {
+                            Ok(rct) => receipts.push(rct),
+                            Err(e) => warn!("Can't make a receipt: {}", e),
+                        }
+                    }
+                }
+            }
+        }
+        receipts
+    }
+}
+
+/// Send event to other TDA and read events and non-transferable receipts it
+/// responds with. TDA which doesn't connect or respond within 5 seconds
+/// fails with `TimedOut` error.
+pub async fn send_event(
+    address: String,
+    last_event: SignedEventMessage,
+) -> io::Result<(
+    Vec<SignedEventMessage>,
+    Vec<receipt::NontransferableReceipt>,
+)> {
+    debug!(peer = %address, "Connecting to TDA");
+    let mut stream = with_timeout(TcpStream::connect(address)).await?;
+
+    let event = last_event
+        .serialize()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    let mut buffer = vec![];
+    with_timeout(async {
+        stream.write_all(&event).await?;
+        debug!("Wrote event to stream");
+
+        // Other TDA responds and closes the connection once it sees we're
+        // done writing, so the whole response is read however long it is.
+        stream.shutdown().await?;
+        stream.read_to_end(&mut buffer).await
+    })
+    .await?;
+    let response = from_utf8(&buffer)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    receipt::parse_stream(response)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Can't parse response"))
+}
+
+/// Send receipt to other TDA. Receipts aren't responded to, so we don't wait
+/// for any response, only for the receipt to be written within 5 seconds.
+pub async fn send_receipt(address: String, receipt: receipt::Receipt) -> io::Result<()> {
+    let mut stream = with_timeout(TcpStream::connect(address)).await?;
+    let receipt = receipt
+        .serialize()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    with_timeout(stream.write_all(&receipt)).await
+}
+
+/// Get KEL from introduction url or from file with stream of its events.
+pub async fn fetch_kel(source: &str) -> io::Result<Vec<SignedEventMessage>> {
+    if source.starts_with("http://") {
+        return oobi::resolve(source).await;
+    }
+    let stream = tokio::fs::read_to_string(source).await?;
+    parse::signed_event_stream(&stream)
+        .map(|(_, kel)| kel)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Can't parse KEL"))
+}
+
+// Learn state of known peers from their introduction urls, given with their
+// addresses.
+async fn resolve_peers(keri: actor::KeriHandle, introductions: Vec<(String, String)>) {
+    for (address, url) in introductions {
+        let introduction = match oobi::Oobi::from_url(&url) {
+            Some(introduction) => introduction,
+            None => {
+                warn!(peer = %address, "Cannot parse introduction url: {}", url);
+                continue;
+            }
+        };
+        let resolved = match oobi::resolve(&url).await {
+            Ok(kel) => keri
+                .call(move |keri| keri.process_kel(&introduction.prefix, &kel))
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        match resolved {
+            Ok(state) => info!(peer = %address, sn = state.sn, "Resolved peer"),
+            Err(e) => warn!(peer = %address, "Can't resolve peer: {}", e),
+        }
+    }
+}
+
+// Run network operation with other host, failing with `TimedOut` error if
+// it doesn't finish within `PEER_TIMEOUT`.
+pub(crate) async fn with_timeout<T>(io: impl Future<Output = io::Result<T>>) -> io::Result<T> {
+    tokio::time::timeout(PEER_TIMEOUT, io)
+        .await
+        .unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "other host didn't respond in time",
+            ))
+        })
+}
+
+// Wait until TDA is asked to stop with SIGINT or SIGTERM.
+#[cfg(unix)]
+async fn shutdown_signal() -> io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        interrupted = tokio::signal::ctrl_c() => interrupted,
+        _ = terminate.recv() => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() -> io::Result<()> {
+    tokio::signal::ctrl_c().await
+}
+
+/// Run TDA described by configuration until it fails or is stopped with
+/// SIGINT or SIGTERM: load or incept its identifiers (restoring primary one
+/// as `recovery` describes, if given), serve HTTP endpoints, hooks, rotation
+/// policy and control socket, accept events and commands on TCP port and
+/// resolve known peers in the background.
+pub async fn run(config: &config::Config, recovery: Option<Recovery>) -> io::Result<()> {
+    let other = |e: String| io::Error::new(io::ErrorKind::Other, e);
+    let address = format!("{}:{}", config.listen.host, config.listen.port);
+    let http_address = format!("{}:{}", config.listen.host, config.listen.http_port);
+
+    let metrics = Arc::new(metrics::Metrics::default());
+
+    // Create instance of KERI
+    let keri_instance = KeriInstance::from_config(config, recovery, Arc::clone(&metrics))
+        .map_err(|e| other(format!("Can't setup identifier: {}", e)))?;
+    let keri_instance = actor::KeriHandle::spawn(keri_instance);
+
+    let primary = keri_instance.call(|keri| keri.primary.clone()).await;
+    let oobi = oobi::Oobi::new(primary.clone(), oobi::CONTROLLER_ROLE);
+    info!(prefix = %oobi.prefix, "TDA introduction: {}", oobi.url(&http_address));
+    let http_keri = keri_instance.clone();
+    let http_listen = http_address.clone();
+    tokio::spawn(async move {
+        if let Err(e) = http::serve(http_listen, http_keri).await {
+            error!("HTTP endpoint failed: {}", e);
+        }
+    });
+
+    let hooks = config.hooks().map_err(|e| other(e.to_string()))?;
+    if !hooks.is_empty() {
+        let (events, log) = keri_instance
+            .call(|keri| {
+                let log = keri.storage.as_ref().map(storage::Storage::hooks_log_path);
+                (keri.events.subscribe(), log)
+            })
+            .await;
+        tokio::spawn(hooks::run_hooks(hooks, events, log));
+    }
+
+    let policy = config.rotation_policy();
+    if policy.is_enabled() {
+        let peers = config.peers.iter().map(|p| p.address.clone()).collect();
+        tokio::spawn(rotation::schedule(
+            keri_instance.clone(),
+            policy,
+            Duration::from_secs(config.rotation.check_interval_secs),
+            peers,
+        ));
+    }
+
+    // Commands sent to control socket are authorized by its file permissions.
+    // Socket file is removed once this function returns.
+    #[cfg(unix)]
+    let _control_socket = match &config.listen.control_socket {
+        Some(path) => {
+            let (listener, file) = control::bind_control_socket(path).map_err(|e| {
+                other(format!(
+                    "Can't bind control socket {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+            info!(socket = %path.display(), "TDA control socket listening");
+            tokio::spawn(control::serve_control_socket(
+                listener,
+                path.clone(),
+                keri_instance.clone(),
+                primary.clone(),
+                http_address.clone(),
+                Arc::clone(&metrics),
+            ));
+            Some(file)
+        }
+        None => None,
+    };
+
+    let tcp_control = config.listen.tcp_control;
+    let mut listener = TcpListener::bind(&address).await?;
+    keri_instance.call(|keri| keri.listening = true).await;
+    info!("TDA Listening on: {}", address);
+
+    // Peers are resolved once TDA listens, so unreachable one can't hold it
+    // back.
+    let introductions = config
+        .peers
+        .iter()
+        .filter_map(|peer| Some((peer.address.clone(), peer.oobi.clone()?)))
+        .collect();
+    tokio::spawn(resolve_peers(keri_instance.clone(), introductions));
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        // Asynchronously wait for an inbound socket, or stop when asked to.
+        let (socket, peer) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            signal = &mut shutdown => {
+                info!("TDA shutting down");
+                return signal;
+            }
+        };
+        let connection = metrics::ConnectionGuard::new(Arc::clone(&metrics));
+        let span = info_span!("connection", peer = %peer, prefix = tracing::field::Empty);
+        let session = control::Session::new(primary.clone(), http_address.clone(), tcp_control);
+        tokio::spawn(
+            control::handle_connection(socket, keri_instance.clone(), session, connection)
+                .instrument(span),
+        );
+    }
+}
@@ -1,67 +1,225 @@
-use std::collections::HashMap;
+//! KEL, receipts and keys of local identifier.
 
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    receipt::{NontransferableReceipt, Receipt},
+    seed::Seed,
+    signer::{Keypair, Signer},
+    storage::StoredLog,
+    traits::{self, ConfigTrait},
+};
 use keri::{
     derivation::basic::Basic, derivation::self_addressing::SelfAddressing,
     derivation::self_signing::SelfSigning, error::Error,
     event::event_data::inception::InceptionEvent, event::event_data::interaction::InteractionEvent,
+    event::event_data::receipt::ReceiptNonTransferable,
     event::event_data::receipt::ReceiptTransferable, event::event_data::rotation::RotationEvent,
     event::event_data::EventData, event::sections::seal::DigestSeal,
     event::sections::seal::EventSeal, event::sections::seal::Seal,
     event::sections::InceptionWitnessConfig, event::sections::KeyConfig,
     event::sections::WitnessConfig, event::Event, event::EventMessage, event::SerializationFormats,
-    event_message::SignedEventMessage, prefix::AttachedSignaturePrefix, prefix::IdentifierPrefix,
-    prefix::Prefix, prefix::SelfAddressingPrefix, state::IdentifierState, util::dfs_serializer,
-};
-use ursa::{
-    keys::{PrivateKey, PublicKey},
-    signatures::ed25519,
-    signatures::SignatureScheme,
+    event_message::SignedEventMessage, prefix::AttachedSignaturePrefix, prefix::BasicPrefix,
+    prefix::IdentifierPrefix, prefix::Prefix, prefix::SelfAddressingPrefix, state::IdentifierState,
+    util::dfs_serializer,
 };
+use ursa::{keys::PublicKey, signatures::ed25519, signatures::SignatureScheme};
 
+/// Keypair of TDA with its index in current key set of the KEL.
+pub struct SigningKey {
+    /// Index of the key in current key set.
+    pub index: u16,
+    /// The keypair, possibly held by external signer.
+    pub keypair: Keypair,
+}
+
+/// Next keys committed to, whose private keys are kept outside of TDA (e.g. on
+/// offline device) and imported only at rotation time.
 #[derive(Clone)]
+pub struct ExternalKeys {
+    /// Signatures required from the next key set.
+    pub threshold: u64,
+    /// Public keys of the next key set.
+    pub public_keys: Vec<PublicKey>,
+}
 
+/// Source of keypairs generated by TDA.
+#[derive(Clone)]
+pub enum KeyGen {
+    /// Operating system randomness, keys can't be derived again.
+    Random,
+    /// Derived from seed, see `Seed::keypair`.
+    Seed {
+        /// Seed the keys are derived from.
+        seed: Seed,
+        /// Account of the identifier, separating its keys from other ones.
+        account: u32,
+    },
+    /// Held by external signer, only public keys enter TDA.
+    External {
+        /// Signer generating and holding the keys.
+        signer: Signer,
+        /// Account of the identifier on the signer.
+        account: u32,
+    },
+}
+
+impl KeyGen {
+    /// Keypair becoming current after `index` establishment events.
+    pub fn keypair(&self, index: u32) -> Result<Keypair, Error> {
+        match self {
+            KeyGen::Random => ed25519::Ed25519Sha512::new()
+                .keypair(Option::None)
+                .map(Keypair::local)
+                .map_err(|e| Error::CryptoError(e)),
+            KeyGen::Seed { seed, account } => seed.keypair(*account, index).map(Keypair::local),
+            KeyGen::External { signer, account } => {
+                signer.public_key(*account, index).map(Keypair::external)
+            }
+        }
+    }
+
+    /// Account of derived or externally held keys, stored in keystore.
+    pub fn account(&self) -> Option<u32> {
+        match self {
+            KeyGen::Random => None,
+            KeyGen::Seed { account, .. } | KeyGen::External { account, .. } => Some(*account),
+        }
+    }
+
+    /// External signer holding the keys, if any.
+    pub fn signer(&self) -> Option<&Signer> {
+        match self {
+            KeyGen::External { signer, .. } => Some(signer),
+            _ => None,
+        }
+    }
+}
+
+/// Keys committed to as next ones by the last establishment event.
+pub enum NextKeys {
+    /// Generated by TDA and kept in its keystore, or held by external signer.
+    Local(Keypair),
+    /// Kept outside of TDA, imported at rotation time.
+    External(ExternalKeys),
+    /// Committed to empty key set: identifier is abandoned or non-transferable,
+    /// no further establishment event can be made.
+    Empty,
+}
+
+impl NextKeys {
+    // Given external keys or local keypair generated for `index`
+    // establishment event.
+    fn new(external: Option<ExternalKeys>, keygen: &KeyGen, index: u32) -> Result<NextKeys, Error> {
+        match external {
+            Some(keys) => Ok(NextKeys::External(keys)),
+            None => Ok(NextKeys::Local(keygen.keypair(index)?)),
+        }
+    }
+
+    /// Public keys of the next key set.
+    pub fn public_keys(&self) -> Vec<PublicKey> {
+        match self {
+            NextKeys::Local(keypair) => vec![keypair.public.clone()],
+            NextKeys::External(keys) => keys.public_keys.clone(),
+            NextKeys::Empty => vec![],
+        }
+    }
+
+    /// Signatures required from the next key set.
+    pub fn threshold(&self) -> u64 {
+        match self {
+            NextKeys::Local(_) => 1,
+            NextKeys::External(keys) => keys.threshold,
+            NextKeys::Empty => 0,
+        }
+    }
+
+    // Digest committing to the whole next key set: derived over concatenated
+    // basic prefixes of the keys, which for a single key is just its prefix
+    // and for abandoned identifier is empty. KeyConfig of keri 0.3 has room
+    // for this one digest only, so rotation has to reveal the whole set and
+    // partial rotation, keeping some of the keys unexposed, isn't possible.
+    fn commitment(&self, derivation: &SelfAddressing) -> SelfAddressingPrefix {
+        let keys: String = self
+            .public_keys()
+            .into_iter()
+            .map(|pk| Basic::Ed25519.derive(pk).to_str())
+            .collect();
+        derivation.derive(keys.as_bytes())
+    }
+}
+
+/// Keys, KEL and receipts of local identifier.
 pub struct LogState {
+    /// events of the KEL, from inception
     pub log: Vec<SignedEventMessage>,
-    pub sigs_map: HashMap<u64, Vec<SignedEventMessage>>,
+    /// receipts of events, by sequence number
+    pub sigs_map: HashMap<u64, Vec<Receipt>>,
+    /// state computed from the KEL
     pub state: IdentifierState,
-    pub keypair: (PublicKey, PrivateKey),
-    pub next_keypair: (PublicKey, PrivateKey),
+    /// current keys held by TDA, a threshold satisfying subset of current key set
+    pub keys: Vec<SigningKey>,
+    /// keys committed to as next ones
+    pub next_keys: NextKeys,
+    /// receipts waiting for validator's establishment event
     pub escrow_sigs: Vec<SignedEventMessage>,
+    /// interaction events superseded by recovery rotation
+    pub superseded: Vec<SignedEventMessage>,
+    /// derivation used for event digests
+    pub derivation: SelfAddressing,
+    /// source of keypairs generated at inception and rotations
+    pub keygen: KeyGen,
+    // what the last event changed, until it's persisted, see `undo`
+    undo: Option<Undo>,
+}
+
+// State replaced by an event, to put back if the event can't be persisted.
+struct Undo {
+    state: IdentifierState,
+    // length of `superseded` before the event
+    superseded: usize,
+    // current keys replaced by establishment event, with its next keys
+    // unless they were kept locally and became current key
+    keys: Option<(Vec<SigningKey>, Option<NextKeys>)>,
+    // receipts and escrowed receipts dropped by recovery
+    receipts: HashMap<u64, Vec<Receipt>>,
+    escrow: Vec<SignedEventMessage>,
 }
+
 impl LogState {
-    // incept a state and keys
-    pub fn new() -> Result<LogState, Error> {
-        let ed = ed25519::Ed25519Sha512::new();
-        let keypair = ed
-            .keypair(Option::None)
-            .map_err(|e| Error::CryptoError(e))?;
-        let next_keypair = ed
-            .keypair(Option::None)
-            .map_err(|e| Error::CryptoError(e))?;
+    /// incept a state and keys, committing to given next keys or newly generated ones
+    pub fn new(
+        derivation: SelfAddressing,
+        witnesses: Vec<BasicPrefix>,
+        next_keys: Option<ExternalKeys>,
+        config_traits: &[ConfigTrait],
+        keygen: KeyGen,
+    ) -> Result<LogState, Error> {
+        let keypair = keygen.keypair(0)?;
+        let next_keys = NextKeys::new(next_keys, &keygen, 1)?;
 
         let icp_data = InceptionEvent {
             key_config: KeyConfig {
                 threshold: 1,
-                public_keys: vec![Basic::Ed25519.derive(keypair.0.clone())],
-                threshold_key_digest: SelfAddressing::Blake3_256.derive(
-                    Basic::Ed25519
-                        .derive(next_keypair.0.clone())
-                        .to_str()
-                        .as_bytes(),
-                ),
+                public_keys: vec![Basic::Ed25519.derive(keypair.public.clone())],
+                threshold_key_digest: next_keys.commitment(&derivation),
+            },
+            witness_config: InceptionWitnessConfig {
+                tally: witnesses.len() as u64,
+                initial_witnesses: witnesses,
             },
-            witness_config: InceptionWitnessConfig::default(),
-            inception_configuration: vec![],
+            inception_configuration: config_traits.iter().map(|t| t.code().to_string()).collect(),
         };
 
         let icp_data_message = EventMessage::get_inception_data(
             &icp_data,
-            SelfAddressing::Blake3_256,
+            derivation.clone(),
             &SerializationFormats::JSON,
         );
 
         let pref = IdentifierPrefix::SelfAddressing(
-            SelfAddressing::Blake3_256.derive(&dfs_serializer::to_vec(&icp_data_message)?),
+            derivation.derive(&dfs_serializer::to_vec(&icp_data_message)?),
         );
 
         let icp_m = Event {
@@ -71,12 +229,49 @@ impl LogState {
         }
         .to_message(&SerializationFormats::JSON)?;
 
-        let sigged = icp_m.sign(vec![AttachedSignaturePrefix::new(
-            SelfSigning::Ed25519Sha512,
-            ed.sign(&icp_m.serialize()?, &keypair.1)
-                .map_err(|e| Error::CryptoError(e))?,
-            0,
-        )]);
+        LogState::incepted(icp_m, keypair, next_keys, derivation, keygen)
+    }
+
+    /// incept non-transferable identifier, whose prefix is its public key and
+    /// which can't rotate keys nor make any events after inception
+    pub fn new_nontransferable(
+        derivation: SelfAddressing,
+        keygen: KeyGen,
+    ) -> Result<LogState, Error> {
+        let keypair = keygen.keypair(0)?;
+        let prefix = Basic::Ed25519NT.derive(keypair.public.clone());
+
+        let icp_m = Event {
+            prefix: IdentifierPrefix::Basic(prefix.clone()),
+            sn: 0,
+            event_data: EventData::Icp(InceptionEvent {
+                key_config: KeyConfig {
+                    threshold: 1,
+                    public_keys: vec![prefix],
+                    threshold_key_digest: NextKeys::Empty.commitment(&derivation),
+                },
+                witness_config: InceptionWitnessConfig {
+                    tally: 0,
+                    initial_witnesses: vec![],
+                },
+                inception_configuration: vec![],
+            }),
+        }
+        .to_message(&SerializationFormats::JSON)?;
+
+        LogState::incepted(icp_m, keypair, NextKeys::Empty, derivation, keygen)
+    }
+
+    // sign inception event and make state of newly incepted identifier
+    fn incepted(
+        icp_m: EventMessage,
+        keypair: Keypair,
+        next_keys: NextKeys,
+        derivation: SelfAddressing,
+        keygen: KeyGen,
+    ) -> Result<LogState, Error> {
+        let keys = vec![SigningKey { index: 0, keypair }];
+        let sigged = icp_m.sign(sign(&keys, &keygen, &icp_m.serialize()?)?);
 
         let s0 = IdentifierState::default().verify_and_apply(&sigged)?;
 
@@ -84,13 +279,119 @@ impl LogState {
             log: vec![sigged],
             sigs_map: HashMap::new(),
             state: s0,
-            keypair,
-            next_keypair,
+            keys,
+            next_keys,
             escrow_sigs: vec![],
+            superseded: vec![],
+            derivation,
+            keygen,
+            undo: None,
+        })
+    }
+
+    // restore state from stored events and keys, verifying the KEL from inception;
+    // `previous` keys are used instead if the last establishment event which
+    // replaced them wasn't stored
+    pub(crate) fn load(
+        stored: StoredLog,
+        keys: Vec<SigningKey>,
+        next_keys: NextKeys,
+        previous: Option<(Vec<SigningKey>, NextKeys)>,
+        derivation: SelfAddressing,
+        keygen: KeyGen,
+    ) -> Result<LogState, Error> {
+        let state = stored
+            .kel
+            .iter()
+            .try_fold(IdentifierState::default(), |state, ev| {
+                state.verify_and_apply(ev)
+            })?;
+        let (keys, next_keys) = match previous {
+            Some(previous) if !keys_match(&state, &keys) && keys_match(&state, &previous.0) => {
+                previous
+            }
+            _ => (keys, next_keys),
+        };
+        if !keys_match(&state, &keys) {
+            return Err(Error::SemanticError(
+                "keystore doesn't match current keys of the KEL".into(),
+            ));
+        }
+
+        let mut sigs_map: HashMap<u64, Vec<Receipt>> = HashMap::new();
+        for rct in stored.receipts {
+            sigs_map
+                .entry(rct.event_message().event.sn)
+                .or_insert_with(|| vec![])
+                .push(rct);
+        }
+
+        Ok(LogState {
+            log: stored.kel,
+            sigs_map,
+            state,
+            keys,
+            next_keys,
+            escrow_sigs: stored.escrow,
+            superseded: stored.superseded,
+            derivation,
+            keygen,
+            undo: None,
         })
     }
 
-    // take a receipt made by validator, verify it and add to sigs_map or escrow
+    /// restore identifier from its KEL, deriving current and next keys from
+    /// the seed or getting them from external signer; fails if the KEL wasn't
+    /// made with such keys
+    pub fn restore(
+        kel: Vec<SignedEventMessage>,
+        derivation: SelfAddressing,
+        keygen: KeyGen,
+    ) -> Result<LogState, Error> {
+        let state = kel
+            .iter()
+            .try_fold(IdentifierState::default(), |state, ev| {
+                state.verify_and_apply(ev)
+            })?;
+        let index = establishments(&kel);
+        if index == 0 {
+            return Err(Error::SemanticError("KEL is empty".into()));
+        }
+        let keys = vec![SigningKey {
+            index: 0,
+            keypair: keygen.keypair(index - 1)?,
+        }];
+        let next_keys = if is_abandoned(&state) || !is_transferable(&state) {
+            NextKeys::Empty
+        } else {
+            NextKeys::Local(keygen.keypair(index)?)
+        };
+        if next_keys.commitment(&derivation) != state.current.threshold_key_digest {
+            return Err(Error::SemanticError(
+                "next keys of the KEL weren't derived from the seed nor held by signer".into(),
+            ));
+        }
+        let stored = StoredLog {
+            kel,
+            receipts: vec![],
+            escrow: vec![],
+            superseded: vec![],
+        };
+        LogState::load(stored, keys, next_keys, None, derivation, keygen)
+    }
+
+    /// check that KEL verifies from inception and leads to current state
+    pub fn verify_kel(&self) -> bool {
+        self.log
+            .iter()
+            .try_fold(IdentifierState::default(), |state, ev| {
+                state.verify_and_apply(ev)
+            })
+            .map(|state| state.sn == self.state.sn && state.last == self.state.last)
+            .unwrap_or(false)
+    }
+
+    /// take a receipt made by validator, verify it and add to sigs_map or escrow
     pub fn add_sig(
         &mut self,
         validator: &IdentifierState,
@@ -115,6 +416,12 @@ impl LogState {
                             // seal pref is the pref of the validator
                             && rct.validator_location_seal.prefix == validator.prefix
                 {
+                    let sn = sigs.event_message.event.sn;
+                    if self.receipted_by(sn).contains(&validator.prefix.to_str()) {
+                        return Err(Error::SemanticError(
+                            "validator already receipted the event".into(),
+                        ));
+                    }
                     if rct.validator_location_seal.event_digest
                         == rct
                             .validator_location_seal
@@ -123,11 +430,13 @@ impl LogState {
                             .derive(&validator.last)
                     {
                         // seal dig is the digest of the last establishment event for the validator, verify the rct
-                        validator.verify(&event.event_message.sign(sigs.signatures.clone()))?;
+                        if !validator.verify(&event.event_message.sign(sigs.signatures.clone()))? {
+                            return Err(Error::SemanticError("invalid receipt signature".into()));
+                        }
                         self.sigs_map
                             .entry(sigs.event_message.event.sn)
                             .or_insert_with(|| vec![])
-                            .push(sigs);
+                            .push(Receipt::Transferable(sigs));
                     } else {
                         // escrow the seal
                         self.escrow_sigs.push(sigs)
@@ -141,104 +450,488 @@ impl LogState {
         }
     }
 
-    pub fn make_rct(&self, event: EventMessage) -> Result<SignedEventMessage, Error> {
+    /// retry escrowed receipts made by validator whose state was updated;
+    /// returns sns of events whose receipts were verified and added to
+    /// sigs_map, invalid receipts are dropped
+    pub fn process_escrow(&mut self, validator: &IdentifierState) -> Vec<u64> {
+        let (retried, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.escrow_sigs)
+            .into_iter()
+            .partition(|rct| match &rct.event_message.event.event_data {
+                EventData::Vrc(rct) => rct.validator_location_seal.prefix == validator.prefix,
+                _ => false,
+            });
+        self.escrow_sigs = kept;
+        let mut added = vec![];
+        for rct in retried {
+            let sn = rct.event_message.event.sn;
+            let escrowed = self.escrow_sigs.len();
+            if self.add_sig(validator, rct).is_ok() && self.escrow_sigs.len() == escrowed {
+                added.push(sn);
+            }
+        }
+        added
+    }
+
+    /// take a receipt made by non-transferable validators, verify each of its
+    /// couplets and add it to sigs_map
+    pub fn add_nontransferable_sig(&mut self, rct: NontransferableReceipt) -> Result<(), Error> {
+        let receipted_digest = match &rct.body.event.event_data {
+            EventData::Rct(data) => data.receipted_event_digest.clone(),
+            _ => return Err(Error::SemanticError("not a receipt".into())),
+        };
+        let event = self
+            .log
+            .get(rct.body.event.sn as usize)
+            .ok_or_else(|| Error::SemanticError("incorrect receipt sn".into()))?;
+        let ser = event.event_message.serialize()?;
+        if rct.body.event.prefix != self.state.prefix
+            || receipted_digest != receipted_digest.derivation.derive(&ser)
+        {
+            return Err(Error::SemanticError("incorrect receipt binding".into()));
+        }
+        if rct.couplets.is_empty() {
+            return Err(Error::SemanticError("receipt has no signatures".into()));
+        }
+        let mut receipted = self.receipted_by(rct.body.event.sn);
+        for (validator, _) in &rct.couplets {
+            if !receipted.insert(validator.to_str()) {
+                return Err(Error::SemanticError(format!(
+                    "{} already receipted the event",
+                    validator.to_str()
+                )));
+            }
+        }
+        for (validator, signature) in &rct.couplets {
+            if !validator.verify(&ser, signature)? {
+                return Err(Error::SemanticError(format!(
+                    "invalid signature of {}",
+                    validator.to_str()
+                )));
+            }
+        }
+        self.sigs_map
+            .entry(rct.body.event.sn)
+            .or_insert_with(|| vec![])
+            .push(Receipt::Nontransferable(rct));
+        Ok(())
+    }
+
+    /// number of validators which receipted event with given sn
+    pub fn receipts(&self, sn: u64) -> usize {
+        self.receipted_by(sn).len()
+    }
+
+    // prefixes of validators which receipted event with given sn
+    fn receipted_by(&self, sn: u64) -> HashSet<String> {
+        self.sigs_map
+            .get(&sn)
+            .into_iter()
+            .flatten()
+            .flat_map(Receipt::validators)
+            .collect()
+    }
+
+    /// identifier committed to empty next key set, see `abandon`
+    pub fn is_abandoned(&self) -> bool {
+        is_abandoned(&self.state)
+    }
+
+    /// configuration traits declared at inception
+    pub fn traits(&self) -> Vec<ConfigTrait> {
+        traits::declared(&self.log)
+    }
+
+    /// identifier prefix is not basic prefix of its only key
+    pub fn is_transferable(&self) -> bool {
+        is_transferable(&self.state)
+    }
+
+    fn ensure_active(&self) -> Result<(), Error> {
+        if self.is_abandoned() {
+            Err(Error::SemanticError("identifier is abandoned".into()))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn ensure_transferable(&self) -> Result<(), Error> {
+        if self.is_transferable() {
+            Ok(())
+        } else {
+            Err(Error::SemanticError(
+                "non-transferable identifier can't make events".into(),
+            ))
+        }
+    }
+
+    /// make receipt of given event: transferable one with seal of our last
+    /// establishment event or, in non-transferable mode, signature couplet
+    pub fn make_rct(&self, event: EventMessage) -> Result<Receipt, Error> {
+        self.ensure_active()?;
         let ser = event.serialize()?;
-        Ok(Event {
-            prefix: event.event.prefix,
-            sn: event.event.sn,
-            event_data: EventData::Vrc(ReceiptTransferable {
-                receipted_event_digest: SelfAddressing::Blake3_256.derive(&ser),
-                validator_location_seal: EventSeal {
-                    prefix: self.state.prefix.clone(),
-                    event_digest: SelfAddressing::Blake3_256.derive(&self.state.last),
-                },
-            }),
+        if let IdentifierPrefix::Basic(signer) = &self.state.prefix {
+            let body = Event {
+                prefix: event.event.prefix,
+                sn: event.event.sn,
+                event_data: EventData::Rct(ReceiptNonTransferable {
+                    receipted_event_digest: self.derivation.derive(&ser),
+                }),
+            }
+            .to_message(&SerializationFormats::JSON)?;
+            let signature = self.keys[0].keypair.sign(&ser, self.keygen.signer())?;
+            return Ok(Receipt::Nontransferable(NontransferableReceipt {
+                body,
+                couplets: vec![(signer.clone(), SelfSigning::Ed25519Sha512.derive(signature))],
+            }));
         }
-        .to_message(&SerializationFormats::JSON)?
-        .sign(vec![AttachedSignaturePrefix::new(
-            SelfSigning::Ed25519Sha512,
-            ed25519::Ed25519Sha512::new()
-                .sign(&ser, &self.keypair.1)
-                .map_err(|e| Error::CryptoError(e))?,
-            0,
-        )]))
+        Ok(Receipt::Transferable(
+            Event {
+                prefix: event.event.prefix,
+                sn: event.event.sn,
+                event_data: EventData::Vrc(ReceiptTransferable {
+                    receipted_event_digest: self.derivation.derive(&ser),
+                    validator_location_seal: EventSeal {
+                        prefix: self.state.prefix.clone(),
+                        event_digest: self.derivation.derive(&self.state.last),
+                    },
+                }),
+            }
+            .to_message(&SerializationFormats::JSON)?
+            .sign(sign(&self.keys, &self.keygen, &ser)?),
+        ))
     }
 
+    /// make interaction event anchoring digest of payload
     pub fn make_ixn(&mut self, payload: &str) -> Result<SignedEventMessage, Error> {
         let dig_seal = DigestSeal {
-            dig: SelfAddressing::Blake3_256.derive(payload.as_bytes()),
+            dig: self.derivation.derive(payload.as_bytes()),
         };
+        self.anchor(vec![Seal::Digest(dig_seal)])
+    }
 
+    /// make interaction event anchoring given seals
+    pub fn anchor(&mut self, seals: Vec<Seal>) -> Result<SignedEventMessage, Error> {
+        self.ensure_transferable()?;
+        self.ensure_active()?;
+        if self.traits().contains(&ConfigTrait::EstablishmentOnly) {
+            return Err(Error::SemanticError(
+                "establishment-only identifier can't make interaction events".into(),
+            ));
+        }
         let ev = Event {
             prefix: self.state.prefix.clone(),
             sn: self.state.sn + 1,
             event_data: EventData::Ixn(InteractionEvent {
-                previous_event_hash: SelfAddressing::Blake3_256.derive(&self.state.last),
-                data: vec![Seal::Digest(dig_seal)],
+                previous_event_hash: self.derivation.derive(&self.state.last),
+                data: seals,
             }),
         }
         .to_message(&SerializationFormats::JSON)?;
 
-        let ixn = ev.sign(vec![AttachedSignaturePrefix::new(
-            SelfSigning::Ed25519Sha512,
-            ed25519::Ed25519Sha512::new()
-                .sign(&ev.serialize().unwrap(), &self.keypair.1)
-                .map_err(|e| Error::CryptoError(e))?,
-            0,
-        )]);
+        let ixn = ev.sign(sign(&self.keys, &self.keygen, &ev.serialize()?)?);
 
-        self.state = self.state.clone().verify_and_apply(&ixn)?;
+        let state = self.state.clone().verify_and_apply(&ixn)?;
+        self.undo = Some(Undo {
+            state: std::mem::replace(&mut self.state, state),
+            superseded: self.superseded.len(),
+            keys: None,
+            receipts: HashMap::new(),
+            escrow: vec![],
+        });
         self.log.push(ixn.clone());
         Ok(ixn)
     }
 
+    /// rotate to locally kept next keys, committing to newly generated ones
     pub fn rotate(&mut self) -> Result<SignedEventMessage, Error> {
-        let ed = ed25519::Ed25519Sha512::new();
-        let keypair = self.next_keypair.clone();
-        // ed
-        // .keypair(Option::None)
-        // .map_err(|e| Error::CryptoError(e))?;
-        let next_keypair = ed
-            .keypair(Option::None)
-            .map_err(|e| Error::CryptoError(e))?;
+        self.rotate_with(vec![], None, vec![])
+    }
+
+    /// Rotate to next keys. If they are kept externally, `imported` keypairs
+    /// must satisfy their threshold; all committed public keys are revealed,
+    /// see `NextKeys::commitment`, but only imported ones sign. Commits to
+    /// `next` keys or, if not given, to newly generated local keypair. Given
+    /// seals are anchored in the rotation event.
+    pub fn rotate_with(
+        &mut self,
+        imported: Vec<Keypair>,
+        next: Option<ExternalKeys>,
+        seals: Vec<Seal>,
+    ) -> Result<SignedEventMessage, Error> {
+        let base = self.state.clone();
+        let next_keys = NextKeys::new(next, &self.keygen, establishments(&self.log) + 1)?;
+        self.establish(base, imported, next_keys, seals)
+    }
 
+    /// Retire the identifier: rotate to next keys, committing to empty next
+    /// key set. No events can be made afterwards.
+    pub fn abandon(&mut self, imported: Vec<Keypair>) -> Result<SignedEventMessage, Error> {
+        let base = self.state.clone();
+        self.establish(base, imported, NextKeys::Empty, vec![])
+    }
+
+    /// Recovery rotation at `sn`, superseding interaction events made from
+    /// `sn` on with compromised current keys. It's signed with next keys,
+    /// unknown to whoever holds current ones.
+    pub fn recover(
+        &mut self,
+        sn: u64,
+        imported: Vec<Keypair>,
+        next: Option<ExternalKeys>,
+    ) -> Result<SignedEventMessage, Error> {
+        let base = recovery_base(&self.log, sn)?;
+        // only interaction events are superseded, so the count of
+        // establishment events doesn't change
+        let next_keys = NextKeys::new(next, &self.keygen, establishments(&self.log) + 1)?;
+        let rot = self.establish(base, imported, next_keys, vec![])?;
+        // receipts of superseded events are not valid for the new one
+        let (receipts, kept) = std::mem::take(&mut self.sigs_map)
+            .into_iter()
+            .partition(|(rct_sn, _)| *rct_sn >= sn);
+        self.sigs_map = kept;
+        let (escrow, kept) = std::mem::take(&mut self.escrow_sigs)
+            .into_iter()
+            .partition(|rct| rct.event_message.event.sn >= sn);
+        self.escrow_sigs = kept;
+        if let Some(undo) = &mut self.undo {
+            undo.receipts = receipts;
+            undo.escrow = escrow;
+        }
+        Ok(rot)
+    }
+
+    // make rotation event following `base` state, replacing all events after
+    // it in the log
+    fn establish(
+        &mut self,
+        base: IdentifierState,
+        imported: Vec<Keypair>,
+        next_keys: NextKeys,
+        seals: Vec<Seal>,
+    ) -> Result<SignedEventMessage, Error> {
+        self.ensure_transferable()?;
+        let public_keys = self.next_keys.public_keys();
+        let imported = match &self.next_keys {
+            NextKeys::Empty => return Err(Error::SemanticError("identifier is abandoned".into())),
+            NextKeys::Local(_) if !imported.is_empty() => {
+                return Err(Error::SemanticError(
+                    "next keys are kept locally, nothing to import".into(),
+                ))
+            }
+            NextKeys::Local(_) => vec![],
+            NextKeys::External(external) => {
+                let mut keys: Vec<SigningKey> = vec![];
+                for keypair in imported {
+                    let index = public_keys
+                        .iter()
+                        .position(|pk| pk.0 == keypair.public.0)
+                        .ok_or_else(|| {
+                            Error::SemanticError("imported key is not committed to".into())
+                        })?;
+                    if keys.iter().all(|key| key.index as usize != index) {
+                        keys.push(SigningKey {
+                            index: index as u16,
+                            keypair,
+                        });
+                    }
+                }
+                if (keys.len() as u64) < external.threshold {
+                    return Err(Error::SemanticError(format!(
+                        "next keys are kept externally, import at least {} of them",
+                        external.threshold
+                    )));
+                }
+                keys
+            }
+        };
         let ev = Event {
-            prefix: self.state.prefix.clone(),
-            sn: self.state.sn + 1,
+            prefix: base.prefix.clone(),
+            sn: base.sn + 1,
             event_data: EventData::Rot(RotationEvent {
-                previous_event_hash: SelfAddressing::Blake3_256.derive(&self.state.last),
+                previous_event_hash: self.derivation.derive(&base.last),
                 key_config: KeyConfig {
-                    threshold: 1,
-                    // public_keys: vec![Basic::Ed25519.derive(self.keypair.0.clone())],
-                    public_keys: vec![Basic::Ed25519.derive(keypair.0.clone())],
-                    threshold_key_digest: SelfAddressing::Blake3_256.derive(
-                        Basic::Ed25519
-                            .derive(next_keypair.0.clone())
-                            .to_str()
-                            .as_bytes(),
-                    ),
+                    threshold: self.next_keys.threshold(),
+                    public_keys: public_keys
+                        .into_iter()
+                        .map(|pk| Basic::Ed25519.derive(pk))
+                        .collect(),
+                    threshold_key_digest: next_keys.commitment(&self.derivation),
                 },
                 witness_config: WitnessConfig::default(),
-                data: vec![],
+                data: seals,
             }),
         }
         .to_message(&SerializationFormats::JSON)?;
 
-        let rot = ev.sign(vec![AttachedSignaturePrefix::new(
-            SelfSigning::Ed25519Sha512,
-            ed25519::Ed25519Sha512::new()
-                .sign(&ev.serialize()?, &keypair.1)
-                .map_err(|e| Error::CryptoError(e))?,
-            0,
-        )]);
+        let data = ev.serialize()?;
+        let signatures = match &self.next_keys {
+            NextKeys::Local(keypair) => vec![signature(0, keypair, &self.keygen, &data)?],
+            _ => sign(&imported, &self.keygen, &data)?,
+        };
+        let rot = ev.sign(signatures);
 
-        self.state = self.state.clone().verify_and_apply(&rot)?;
+        let state = base.verify_and_apply(&rot)?;
+        let previous_state = std::mem::replace(&mut self.state, state);
+        let previous_superseded = self.superseded.len();
 
+        let superseded = self.log.drain(rot.event_message.event.sn as usize..);
+        self.superseded.extend(superseded);
         self.log.push(rot.clone());
 
-        self.keypair = keypair;
-        self.next_keypair = next_keypair;
+        // next keys become current ones, old current keys are kept only
+        // until the event is persisted
+        let (current, previous_next) = match std::mem::replace(&mut self.next_keys, next_keys) {
+            NextKeys::Local(keypair) => (vec![SigningKey { index: 0, keypair }], None),
+            next_keys => (imported, Some(next_keys)),
+        };
+        let previous_keys = std::mem::replace(&mut self.keys, current);
+        self.undo = Some(Undo {
+            state: previous_state,
+            superseded: previous_superseded,
+            keys: Some((previous_keys, previous_next)),
+            receipts: HashMap::new(),
+            escrow: vec![],
+        });
 
         Ok(rot)
     }
+
+    /// current and next keys replaced by the last establishment event, until
+    /// it's persisted; next keys are `None` if they were kept locally and
+    /// became current key
+    pub fn replaced_keys(&self) -> Option<(&[SigningKey], Option<&NextKeys>)> {
+        self.undo
+            .as_ref()
+            .and_then(|undo| undo.keys.as_ref())
+            .map(|(keys, next_keys)| (keys.as_slice(), next_keys.as_ref()))
+    }
+
+    /// the last event was persisted and can't be undone anymore; replaced keys
+    /// are dropped
+    pub fn settle(&mut self) {
+        self.undo = None;
+    }
+
+    /// put back the state replaced by the last event, which couldn't be
+    /// persisted
+    pub fn undo(&mut self) {
+        let undo = match self.undo.take() {
+            Some(undo) => undo,
+            None => return,
+        };
+        let restored = self.superseded.split_off(undo.superseded);
+        self.log
+            .truncate(undo.state.sn as usize + 1 - restored.len());
+        self.log.extend(restored);
+        self.state = undo.state;
+        if let Some((keys, next_keys)) = undo.keys {
+            let current = std::mem::replace(&mut self.keys, keys);
+            self.next_keys = match next_keys {
+                Some(next_keys) => next_keys,
+                None => NextKeys::Local(
+                    current
+                        .into_iter()
+                        .next()
+                        .expect("local next key became current key")
+                        .keypair,
+                ),
+            };
+        }
+        self.sigs_map.extend(undo.receipts);
+        self.escrow_sigs.extend(undo.escrow);
+    }
+}
+
+/// Non-transferable identifiers use their only key as prefix.
+pub fn is_transferable(state: &IdentifierState) -> bool {
+    !matches!(state.prefix, IdentifierPrefix::Basic(_))
+}
+
+/// Last establishment event of transferable identifier committed to empty next
+/// key set.
+pub fn is_abandoned(state: &IdentifierState) -> bool {
+    let commitment = &state.current.threshold_key_digest;
+    is_transferable(state) && *commitment == commitment.derivation.derive(&[])
+}
+
+// Number of establishment events in given KEL, which is also the index of
+// next keys in seeded key derivation.
+fn establishments(kel: &[SignedEventMessage]) -> u32 {
+    kel.iter()
+        .filter(|ev| {
+            matches!(
+                ev.event_message.event.event_data,
+                EventData::Icp(_) | EventData::Rot(_)
+            )
+        })
+        .count() as u32
+}
+
+// Derivation of basic prefixes of identifier keys.
+// keys are current keys of the state, at their indexes
+fn keys_match(state: &IdentifierState, keys: &[SigningKey]) -> bool {
+    let basic = key_derivation(state);
+    !keys.is_empty()
+        && keys.iter().all(|key| {
+            state.current.public_keys.get(key.index as usize)
+                == Some(&basic.derive(key.keypair.public.clone()))
+        })
+}
+
+fn key_derivation(state: &IdentifierState) -> Basic {
+    if is_transferable(state) {
+        Basic::Ed25519
+    } else {
+        Basic::Ed25519NT
+    }
+}
+
+/// State of the identifier before event at `sn`, which recovery rotation is
+/// going to supersede together with all events after it. Only interaction
+/// events can be superseded.
+pub fn recovery_base(kel: &[SignedEventMessage], sn: u64) -> Result<IdentifierState, Error> {
+    if sn == 0 || sn as usize >= kel.len() {
+        return Err(Error::SemanticError(format!(
+            "no event to supersede at sn {}",
+            sn
+        )));
+    }
+    let establishment = kel[sn as usize..]
+        .iter()
+        .any(|ev| !matches!(ev.event_message.event.event_data, EventData::Ixn(_)));
+    if establishment {
+        return Err(Error::SemanticError(
+            "only interaction events can be superseded".into(),
+        ));
+    }
+    kel[..sn as usize]
+        .iter()
+        .try_fold(IdentifierState::default(), |state, ev| {
+            state.verify_and_apply(ev)
+        })
+}
+
+// sign data with each of given keys, attaching signatures at key indexes;
+// keys not held by TDA are signed with external signer of `keygen`
+fn sign(
+    keys: &[SigningKey],
+    keygen: &KeyGen,
+    data: &[u8],
+) -> Result<Vec<AttachedSignaturePrefix>, Error> {
+    keys.iter()
+        .map(|key| signature(key.index, &key.keypair, keygen, data))
+        .collect()
+}
+
+fn signature(
+    index: u16,
+    keypair: &Keypair,
+    keygen: &KeyGen,
+    data: &[u8],
+) -> Result<AttachedSignaturePrefix, Error> {
+    Ok(AttachedSignaturePrefix::new(
+        SelfSigning::Ed25519Sha512,
+        keypair.sign(data, keygen.signer())?,
+        index,
+    ))
 }
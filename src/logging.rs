@@ -0,0 +1,55 @@
+//! Tracing setup and descriptions of events and commands safe to log.
+
+use std::{fmt, str::FromStr};
+
+use keri::{event::event_data::EventData, event_message::SignedEventMessage, prefix::Prefix};
+use tracing::Level;
+
+use crate::config::LogConfig;
+
+/// Setup global tracing subscriber, writing human readable or JSON lines to
+/// stdout.
+pub fn init(config: &LogConfig) {
+    // Level is validated together with the rest of configuration.
+    let level = Level::from_str(&config.level).unwrap_or(Level::INFO);
+    let builder = tracing_subscriber::fmt().with_max_level(level);
+    if config.format == "json" {
+        builder.json().init();
+    } else {
+        builder.init();
+    }
+}
+
+/// Name of event type, as used in logs and metrics.
+pub fn event_type(event: &SignedEventMessage) -> &'static str {
+    match event.event_message.event.event_data {
+        EventData::Icp(_) => "Icp",
+        EventData::Rot(_) => "Rot",
+        EventData::Ixn(_) => "Ixn",
+        EventData::Vrc(_) => "Vrc",
+        EventData::Rct(_) => "Rct",
+        _ => "Other",
+    }
+}
+
+/// Event description which is safe to log: type, sn and prefix, but never
+/// event data nor signatures.
+pub struct EventSummary<'a>(pub &'a SignedEventMessage);
+
+impl<'a> fmt::Display for EventSummary<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let event = &self.0.event_message.event;
+        write!(
+            f,
+            "{} sn={} prefix={}",
+            event_type(self.0),
+            event.sn,
+            event.prefix.to_str()
+        )
+    }
+}
+
+/// Control command name without its arguments (e.g. IXN payload), safe to log.
+pub fn command_name(msg: &str) -> &str {
+    msg.split_whitespace().next().unwrap_or_default()
+}
@@ -1,350 +1,295 @@
-use std::{error::Error, str::from_utf8, sync::Arc};
-use tokio::net::TcpListener;
-use tokio::net::TcpStream;
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    sync::Mutex,
-};
+use std::{error::Error, path::PathBuf};
 
-use clap::App as clapapp;
-use clap::Arg;
-use keri::{
-    event::event_data::EventData, event_message::parse, event_message::SignedEventMessage,
-    prefix::Prefix, state::IdentifierState,
-};
+use tracing::error;
 
-mod log_state;
+use clap::App as clapapp;
+use clap::{Arg, ArgMatches, SubCommand};
+use tda_standalone::{config, control, logging, seed};
 
-#[derive(Clone)]
-struct KeriInstance {
-    log: log_state::LogState,
-    state: IdentifierState,
-}
+// Read configuration file if given and override its values with command line
+// arguments and environment variables.
+fn load_config(matches: &ArgMatches) -> Result<config::Config, config::ConfigError> {
+    let mut config = match matches.value_of("config") {
+        Some(path) => config::Config::from_file(&PathBuf::from(path))?,
+        None => config::Config::default(),
+    };
 
-impl KeriInstance {
-    fn new(log: log_state::LogState, state: IdentifierState) -> Self {
-        KeriInstance {
-            log: log,
-            state: state,
-        }
+    let parse_port = |name: &str| -> Result<Option<u16>, config::ConfigError> {
+        matches
+            .value_of(name)
+            .map(|port| {
+                port.parse::<u16>().map_err(|_| {
+                    config::ConfigError::Invalid(format!("invalid {} value: {}", name, port))
+                })
+            })
+            .transpose()
+    };
+    if let Some(host) = matches.value_of("host") {
+        config.listen.host = host.to_string();
     }
-
-    fn parse_event(&mut self, event: &str) -> Vec<u8> {
-        let mut response: Vec<u8> = vec![];
-
-        // Deserialize signed msg
-        let msg = parse::signed_message(event)
-            .expect("Can't parse event msg")
-            .1;
-        let m = msg.clone();
-
-        println!("Process keri event ...");
-
-        // Process message.
-        response = match msg.event_message.event.event_data {
-            // if it's receipt message, verify it and add to sigs_map.
-            EventData::Vrc(_) => {
-                println!("Recipt message, verifying ...");
-                self.log
-                    .add_sig(&self.state.clone(), msg)
-                    .expect("Can't verify receipt msg");
-                println!(
-                    "Got receipt of {:?}-th event",
-                    m.clone().event_message.event.sn
-                );
-                vec![]
-            }
-            // if it's inception event respond with last establishment message and receipt message.
-            EventData::Icp(_) => {
-                self.state = self
-                    .state
-                    .clone()
-                    .verify_and_apply(&msg)
-                    .expect("Can't verify received message.");
-                let receipt = self
-                    .log
-                    .make_rct(msg.event_message)
-                    .expect("Can't make a receipt");
-
-                // Respond with last establishment message and receipt message.
-                let last_est = self
-                    .log
-                    .log
-                    .last()
-                    .expect("There is no last alice's establishment event.");
-                let respond =
-                    [last_est.serialize().unwrap(), receipt.serialize().unwrap()].concat();
-                println!(
-                    "Got inception event from {:?}.",
-                    m.event_message.event.prefix.to_str()
-                );
-                respond
-            }
-            // if it's rotation event, respond with receipt event.
-            EventData::Rot(_) | EventData::Ixn(_) => {
-                self.state = self
-                    .state
-                    .clone()
-                    .verify_and_apply(&msg)
-                    .expect("Can't verify received message.");
-                let receipt = self
-                    .log
-                    .make_rct(msg.event_message)
-                    .expect("Can't make a receipt");
-
-                let respond = receipt.serialize().unwrap();
-                println!(
-                    "Got rotation event of sn = {:?} from {:?}.",
-                    m.event_message.event.sn,
-                    m.event_message.event.prefix.to_str()
-                );
-                respond
-            }
-            _ => response,
-        };
-        response
+    if let Some(port) = parse_port("port")? {
+        config.listen.port = port;
+    }
+    if let Some(port) = parse_port("http-port")? {
+        config.listen.http_port = port;
+    }
+    if let Some(path) = matches.value_of("control-socket") {
+        config.listen.control_socket = Some(PathBuf::from(path));
+    }
+    if matches.is_present("tcp-control") {
+        config.listen.tcp_control = true;
+    }
+    if matches.is_present("no-tcp-control") {
+        config.listen.tcp_control = false;
+    }
+    if matches.is_present("no-control-socket") {
+        config.listen.control_socket = None;
+    }
+    if let Some(path) = matches.value_of("storage") {
+        config.storage.path = Some(PathBuf::from(path));
+    }
+    if let Some(path) = matches.value_of("keystore") {
+        config.storage.keystore = Some(PathBuf::from(path));
+    }
+    if matches.is_present("non-transferable") {
+        config.identifier.transferable = false;
+    }
+    if let Some(path) = matches.value_of("mnemonic-file") {
+        config.identifier.mnemonic_file = Some(PathBuf::from(path));
+    }
+    if let Some(path) = matches.value_of("signer-socket") {
+        config.signer.kind = "agent".to_string();
+        config.signer.socket = Some(PathBuf::from(path));
+    }
+    if matches.is_present("lock-memory") {
+        config.signer.lock_memory = true;
+    }
+    if let Some(codes) = matches.value_of("traits") {
+        config.identifier.traits = codes
+            .split(',')
+            .map(str::trim)
+            .filter(|code| !code.is_empty())
+            .map(String::from)
+            .collect();
+    }
+    if let Some(level) = matches.value_of("log-level") {
+        config.log.level = level.to_string();
+    }
+    if let Some(format) = matches.value_of("log-format") {
+        config.log.format = format.to_string();
     }
-}
-
-//     pub fn process_response(&mut self, response: Vec<SignedEventMessage>, address: String ){
-//         // Response is vec of SignedMessage. Handle it one by one. Apply establishment event
-//         // to other_state and handle receipt event.
-//         for sig_msg in response {
-//             match sig_msg.event_message.event.event_data {
-//                 // If sig_msg is establishment event, update self.bob_state, and send receipt to bob.
-//                 keri::event::event_data::EventData::Icp(_)
-//                 | keri::event::event_data::EventData::Rot(_) => {
-//                     self.bob_state =
-//                         self.bob_state.clone().verify_and_apply(&sig_msg).expect("Can't verify bob's mesage.");
-//                     // Send receipt of message sig_msg.
-//                     let rcpt = self.log.make_rct(sig_msg.event_message);
-//                     send_to_other(address.to_string(), &rcpt.unwrap());
-//                 }
-//                 // If sig_msg is receipt event, verify it and add to sigs_map.
-//                 keri::event::event_data::EventData::Vrc(_) => self
-//                     .log
-//                     .add_sig(self.bob_state.clone(), sig_msg)
-//                     .expect("Can't verify receipt msg."),
-//                 _ => {
-
-//                 }
-//             }
-//         }
-//     }
-// }
-
-async fn send_event(address: String, last_event: SignedEventMessage) -> Vec<SignedEventMessage> {
-    println!("Connecting to TDA on: {}", address);
-    let mut stream = TcpStream::connect(address).await.unwrap();
-
-    let event = last_event
-        .serialize()
-        .expect("Can't deserialize event")
-        .clone();
-    let result = stream.write(&event).await;
-    println!("wrote to stream; success={:?}", result.is_ok());
-
-    // Read the receipt
-    let mut buffer = [0; 1024];
 
-    let size = stream.read(&mut buffer[..]).await.expect("Can't get size");
-    let receipt_msgs = parse::signed_event_stream(from_utf8(&buffer[..size]).unwrap())
-        .unwrap()
-        .1;
-    receipt_msgs
+    config.validate()?;
+    Ok(config)
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    // Parse command line arguments.
+    // Parse command line arguments. Each of them can be also set with
+    // environment variable, both take precedence over configuration file.
     let matches = clapapp::new("get-command-line-args")
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .env("TDA_CONFIG")
+                .help("path to TOML configuration file")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("host")
                 .short('H'.to_string())
+                .env("TDA_HOST")
                 .help("hostname on which we would listen, default: localhost")
                 .takes_value(true),
         )
         .arg(
             Arg::with_name("port")
                 .short('P'.to_string())
+                .env("TDA_PORT")
                 .help("port on which we would open TCP connections, default: 49152")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("http-port")
+                .long("http-port")
+                .env("TDA_HTTP_PORT")
+                .help("port on which we would serve HTTP endpoints (OOBI), default: 49153")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("control-socket")
+                .long("control-socket")
+                .env("TDA_CONTROL_SOCKET")
+                .help("Unix socket on which we would accept control commands")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no-control-socket")
+                .long("no-control-socket")
+                .conflicts_with("control-socket")
+                .help("don't create control socket, requires --tcp-control"),
+        )
+        .arg(
+            Arg::with_name("tcp-control")
+                .long("tcp-control")
+                .help("accept control commands on TCP port too"),
+        )
+        .arg(
+            Arg::with_name("no-tcp-control")
+                .long("no-tcp-control")
+                .conflicts_with("tcp-control")
+                .help("accept only KERI events on TCP port, no control commands (default)"),
+        )
+        .arg(
+            Arg::with_name("storage")
+                .long("storage")
+                .env("TDA_STORAGE")
+                .help("directory in which KEL and receipts are kept")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("keystore")
+                .long("keystore")
+                .env("TDA_KEYSTORE")
+                .help("keystore file, default: keystore.json in storage directory")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("non-transferable")
+                .long("non-transferable")
+                .help("incept non-transferable identifier, which only makes receipts"),
+        )
+        .arg(
+            Arg::with_name("traits")
+                .long("traits")
+                .env("TDA_TRAITS")
+                .help("comma separated configuration traits of new identifier: EO, DND")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mnemonic-file")
+                .long("mnemonic-file")
+                .env("TDA_MNEMONIC_FILE")
+                .help("file with mnemonic phrase from which keys are derived")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("generate-mnemonic")
+                .long("generate-mnemonic")
+                .help("print new mnemonic phrase and exit"),
+        )
+        .arg(
+            Arg::with_name("recover-from")
+                .long("recover-from")
+                .help("introduction url or KEL file of identifier to recover with keys derived from mnemonic")
+                .requires("next-account")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("next-account")
+                .long("next-account")
+                .help("account of the next identifier incepted after recovery, above any used on the lost machine")
+                .requires("recover-from")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("signer-socket")
+                .long("signer-socket")
+                .env("TDA_SIGNER_SOCKET")
+                .help("Unix socket of agent which holds private keys and signs for TDA")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("lock-memory")
+                .long("lock-memory")
+                .help("Lock all memory, so private keys are never swapped to disk"),
+        )
+        .arg(
+            Arg::with_name("log-level")
+                .long("log-level")
+                .env("TDA_LOG_LEVEL")
+                .help("one of: error, warn, info, debug, trace, default: info")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-format")
+                .long("log-format")
+                .env("TDA_LOG_FORMAT")
+                .help("text or json, default: text")
+                .takes_value(true),
+        )
+        .subcommand(
+            SubCommand::with_name("ctl")
+                .about("send control command to running TDA and print its response")
+                .arg(
+                    Arg::with_name("command")
+                        .help("command with its arguments, e.g. IDS")
+                        .required(true)
+                        .multiple(true),
+                ),
+        )
         .get_matches();
 
-    let host = matches.value_of("host").unwrap_or("localhost");
-    let port = matches.value_of("port").unwrap_or("49152");
-    let address = [host, ":", port].concat();
-
-    // Create instance of KERI
-    let mut keri_instance = Arc::new(Mutex::new(KeriInstance::new(
-        log_state::LogState::new().unwrap(),
-        IdentifierState::default(),
-    )));
-
-    let mut listener = TcpListener::bind(&address).await?;
-    println!("TDA Listening on: {}", address);
-
-    loop {
-        // Asynchronously wait for an inbound socket.
-        let (mut socket, _) = listener.accept().await?;
-        let keri = Arc::clone(&keri_instance);
-        tokio::spawn(async move {
-            let mut buf = [0; 1024];
-
-            // In a loop, read data from the socket
-            loop {
-                let n = socket
-                    .read(&mut buf)
-                    .await
-                    .expect("failed to read data from socket");
-
-                if n == 0 {
-                    return;
-                } else {
-                    // Read message as utf string
-                    let msg = from_utf8(&buf[..n]).unwrap();
-                    // Ignore messages shorted then 4 bytes
-                    if n > 3 {
-                        // Read first 4 characters to see if it match with TDA commands
-                        let command = &msg[0..3];
-                        match command {
-                            "IDS" => {
-                                println!("Identifier state");
-                                let keri = keri.lock().await;
-                                let ids = keri.log.state.clone();
-                                println!("SN: {}", ids.sn);
-                                let msg = format!("SN: {}\n", ids.sn);
-
-                                socket
-                                    .write_all(&msg.as_bytes())
-                                    .await
-                                    .expect("failed to write data to socket");
-                            }
-                            "LSE" => {
-                                println!("Current KEL:");
-                                let keri = keri.lock().await;
-                                let kel: Vec<SignedEventMessage> = keri.log.log.clone();
-                                for signed_message in &kel {
-                                    println!(
-                                        "{:?}",
-                                        &signed_message.event_message.event.event_data
-                                    );
-                                    let msg = format!(
-                                        "{:?}\n",
-                                        &signed_message.event_message.event.event_data
-                                    );
+    if matches.is_present("generate-mnemonic") {
+        let (phrase, _) = seed::Seed::generate();
+        println!("{}", phrase);
+        return Ok(());
+    }
 
-                                    socket
-                                        .write_all(&msg.as_bytes())
-                                        .await
-                                        .expect("failed to write data to socket");
-                                }
-                            }
-                            "LSR" => {
-                                println!("Current KERL:");
-                                let keri = keri.lock().await;
-                                let kerl = keri.log.sigs_map.clone();
+    let config = match load_config(&matches) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Configuration error: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-                                for (key, val) in &kerl {
-                                    let msg = format!("{}: {:?}\n", key, val);
-                                    socket
-                                        .write_all(&msg.as_bytes())
-                                        .await
-                                        .expect("failed to write data to socket");
-                                }
-                            }
-                            "SEN" => {
-                                println!("Received command: {}", msg);
-                                // Simple parsing of the command
-                                let mut iter = msg.split_whitespace();
-                                iter.next();
-                                // Get host to where send the message
-                                let host = iter.next().unwrap();
-                                let port = iter.next().unwrap();
-                                let address = [host, ":", port].concat();
-                                println!("Send my events to {}", address.clone());
-                                let mut keri = keri.lock().await;
-                                let last_event = keri.log.log.last().unwrap().clone();
+    if let Some(ctl) = matches.subcommand_matches("ctl") {
+        let command: Vec<&str> = ctl.values_of("command").unwrap_or_default().collect();
+        match control::send_command(&config.listen, &command.join(" ")).await {
+            Ok(response) => print!("{}", String::from_utf8_lossy(&response)),
+            Err(e) => {
+                eprintln!("Can't send command: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
 
-                                // We can get more than one event in response.
-                                // Not only receipt events, but also other
-                                // types.
-                                let response = send_event(address.clone(), last_event).await;
-                                println!("Got receipts: {:?}", response);
+    logging::init(&config.log);
 
-                                for sig_msg in response {
-                                    match sig_msg.event_message.event.event_data {
-                                        // If sig_msg is receipt event, verify
-                                        // it and add to sigs_map.
-                                        EventData::Vrc(_) => {
-                                            let validator = keri.clone().state;
-                                            println!("Got receipt from {}\n", address.clone());
-                                            keri.log
-                                                .add_sig(&validator, sig_msg)
-                                                .expect("Can't verify receipt msg.");
-                                        }
-                                        // If sig_msg is event of other type,
-                                        // update keri.state and send receipt of
-                                        // it to responder.
-                                        _ => {
-                                            println!("Got event from {}", address.clone());
-                                            keri.state = keri
-                                                .state
-                                                .clone()
-                                                .verify_and_apply(&sig_msg)
-                                                .expect("Can't verify mesage from response.");
-                                            // Send receipt of message sig_msg.
-                                            let rcpt = keri
-                                                .log
-                                                .make_rct(sig_msg.event_message)
-                                                .expect("Can't make a receipt");
-                                            send_event(address.clone(), rcpt);
-                                        }
-                                    }
-                                }
-                            }
-                            "ROT" => {
-                                println!("Generate rotate event");
-                                let mut keri = keri.lock().await;
-                                keri.log.rotate();
-                            }
-                            "IXN" => {
-                                let mut iter = msg.split_whitespace();
-                                iter.next();
-                                // Get payload
-                                let payload = iter.next();
-                                match payload {
-                                    Some(p) => {
-                                        let mut keri = keri.lock().await;
-                                        keri.log.make_ixn(p);
-                                    }
-                                    None => {
-                                        socket
-                                            .write_all(b"Cannot parse the payload\n")
-                                            .await
-                                            .expect("failed to write data to socket");
-                                    }
-                                }
-                            }
-                            // If we do not match any command then probably we are getting keri events
-                            _ => {
-                                println!("KERI event message. Processing ...");
-                                println!("Keri event: {} ", msg);
-                                let mut keri = keri.lock().await;
-                                let receipt = keri.parse_event(&msg.to_string());
-                                println!("Respond with {:?}", String::from_utf8(receipt.clone()));
-                                // Send back the receipt
+    // Before any key material is loaded.
+    if config.signer.lock_memory {
+        tda_standalone::lock_memory();
+    }
 
-                                socket
-                                    .write_all(&receipt)
-                                    .await
-                                    .expect("failed to write data to socket");
-                            }
-                        }
-                    }
-                }
+    let next_account = match matches.value_of("next-account").map(str::parse::<u32>) {
+        Some(Ok(account)) => account,
+        Some(Err(_)) => {
+            error!("Invalid next account, expected a number");
+            std::process::exit(1);
+        }
+        None => 0,
+    };
+    // Fetch KEL of identifier to recover.
+    let recovery = match matches.value_of("recover-from") {
+        Some(url) if config.identifier.mnemonic_file.is_none() && config.signer.kind == "local" => {
+            error!(url = %url, "Recovery requires mnemonic file or external signer");
+            std::process::exit(1);
+        }
+        Some(url) => match tda_standalone::fetch_kel(url).await {
+            Ok(kel) => Some(tda_standalone::Recovery { kel, next_account }),
+            Err(e) => {
+                error!(url = %url, "Can't fetch KEL to recover: {}", e);
+                std::process::exit(1);
             }
-        });
+        },
+        None => None,
+    };
+
+    if let Err(e) = tda_standalone::run(&config, recovery).await {
+        error!("{}", e);
+        std::process::exit(1);
     }
+    Ok(())
 }
@@ -0,0 +1,192 @@
+//! Metrics of TDA, rendered for `/metrics` endpoint.
+
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+// Event types counted separately, see `logging::event_type`.
+const EVENT_TYPES: [&str; 6] = ["Icp", "Rot", "Ixn", "Vrc", "Rct", "Other"];
+// Upper bounds (in seconds) of `SEN` round-trip latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+/// Counters describing TDA behaviour, rendered in Prometheus text format.
+#[derive(Default)]
+pub struct Metrics {
+    received_events: [AtomicU64; 6],
+    verification_failures: AtomicU64,
+    receipts_stored: AtomicU64,
+    receipts_escrowed: AtomicU64,
+    sen_latency_buckets: [AtomicU64; 10],
+    sen_latency_micros: AtomicU64,
+    sen_count: AtomicU64,
+    active_connections: AtomicI64,
+}
+
+impl Metrics {
+    /// Count received event of given type, e.g. `Icp`.
+    pub fn event_received(&self, event_type: &str) {
+        let i = EVENT_TYPES
+            .iter()
+            .position(|t| *t == event_type)
+            .unwrap_or(EVENT_TYPES.len() - 1);
+        self.received_events[i].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count event or receipt which failed verification.
+    pub fn verification_failed(&self) {
+        self.verification_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count receipt added to sigs_map.
+    pub fn receipt_stored(&self) {
+        self.receipts_stored.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count receipt escrowed until validator's establishment event is known.
+    pub fn receipt_escrowed(&self) {
+        self.receipts_escrowed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record how long sending event to peers took.
+    pub fn sen_finished(&self, latency: Duration) {
+        let seconds = latency.as_secs_f64();
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&self.sen_latency_buckets) {
+            if seconds <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.sen_latency_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+        self.sen_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Render metrics in Prometheus text exposition format. Current sn of
+    /// each local identifier, by prefix, is taken from its state at the time
+    /// of scraping.
+    pub fn render(&self, current_sns: &[(String, u64)]) -> String {
+        let mut out = String::new();
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        header(
+            &mut out,
+            "tda_received_events_total",
+            "counter",
+            "Number of received KERI events by type.",
+        );
+        for (event_type, counter) in EVENT_TYPES.iter().zip(&self.received_events) {
+            writeln!(
+                out,
+                "tda_received_events_total{{type=\"{}\"}} {}",
+                event_type,
+                load(counter)
+            )
+            .unwrap();
+        }
+
+        counter(
+            &mut out,
+            "tda_verification_failures_total",
+            "Number of received events and receipts which failed verification.",
+            load(&self.verification_failures),
+        );
+        counter(
+            &mut out,
+            "tda_receipts_stored_total",
+            "Number of verified receipts stored in receipt log.",
+            load(&self.receipts_stored),
+        );
+        counter(
+            &mut out,
+            "tda_receipts_escrowed_total",
+            "Number of receipts put into escrow.",
+            load(&self.receipts_escrowed),
+        );
+
+        header(
+            &mut out,
+            "tda_sen_round_trip_seconds",
+            "histogram",
+            "Latency of sending events to other TDA with SEN command.",
+        );
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&self.sen_latency_buckets) {
+            writeln!(
+                out,
+                "tda_sen_round_trip_seconds_bucket{{le=\"{}\"}} {}",
+                bound,
+                load(bucket)
+            )
+            .unwrap();
+        }
+        let count = load(&self.sen_count);
+        writeln!(
+            out,
+            "tda_sen_round_trip_seconds_bucket{{le=\"+Inf\"}} {}",
+            count
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "tda_sen_round_trip_seconds_sum {}",
+            load(&self.sen_latency_micros) as f64 / 1_000_000.0
+        )
+        .unwrap();
+        writeln!(out, "tda_sen_round_trip_seconds_count {}", count).unwrap();
+
+        header(
+            &mut out,
+            "tda_active_connections",
+            "gauge",
+            "Number of open TCP connections.",
+        );
+        writeln!(
+            out,
+            "tda_active_connections {}",
+            self.active_connections.load(Ordering::Relaxed)
+        )
+        .unwrap();
+
+        header(
+            &mut out,
+            "tda_current_sn",
+            "gauge",
+            "Sequence number of the last event in KEL of local identifier.",
+        );
+        for (prefix, sn) in current_sns {
+            writeln!(out, "tda_current_sn{{prefix=\"{}\"}} {}", prefix, sn).unwrap();
+        }
+
+        out
+    }
+}
+
+/// Counts connection as active until dropped.
+pub struct ConnectionGuard(Arc<Metrics>);
+
+impl ConnectionGuard {
+    /// Count new active connection.
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        metrics.active_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(metrics)
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, "counter", help);
+    writeln!(out, "{} {}", name, value).unwrap();
+}
@@ -0,0 +1,68 @@
+use std::{io, str::from_utf8};
+
+use keri::{event_message::parse, event_message::SignedEventMessage};
+
+use crate::http;
+
+// Role of the endpoint which serves identifier's own KEL.
+pub const CONTROLLER_ROLE: &str = "controller";
+pub const KEL_CONTENT_TYPE: &str = "application/keri+json";
+
+// Out-of-band introduction: tells where KEL of given identifier can be found.
+pub struct Oobi {
+    pub prefix: String,
+    pub role: String,
+}
+
+impl Oobi {
+    pub fn new(prefix: String, role: &str) -> Self {
+        Oobi {
+            prefix,
+            role: role.to_string(),
+        }
+    }
+
+    // Introduction url served from TDA HTTP endpoint on given address.
+    pub fn url(&self, http_address: &str) -> String {
+        format!("http://{}/oobi/{}/{}", http_address, self.prefix, self.role)
+    }
+
+    // Parse oobi from url path: `/oobi/{prefix}` or `/oobi/{prefix}/{role}`.
+    // Role defaults to controller.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let mut segments = path.trim_matches('/').split('/');
+        if segments.next()? != "oobi" {
+            return None;
+        }
+        let prefix = segments.next().filter(|p| !p.is_empty())?;
+        let role = segments.next().unwrap_or(CONTROLLER_ROLE);
+        if segments.next().is_some() {
+            return None;
+        }
+        Some(Oobi::new(prefix.to_string(), role))
+    }
+
+    // Parse oobi from full introduction url.
+    pub fn from_url(url: &str) -> Option<Self> {
+        let rest = url.splitn(2, "://").nth(1)?;
+        let path = &rest[rest.find('/')?..];
+        Oobi::from_path(path)
+    }
+}
+
+// Fetch KEL published under given introduction url.
+pub async fn resolve(url: &str) -> io::Result<Vec<SignedEventMessage>> {
+    let (status, body) = http::get(url).await?;
+    if status != 200 {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Got {} status from {}", status, url),
+        ));
+    }
+
+    let body =
+        from_utf8(&body).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    parse::signed_event_stream(body)
+        .map(|(_, kel)| kel)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Can't parse KEL"))
+}
@@ -0,0 +1,180 @@
+use std::{path::Path, ptr, sync::Mutex};
+
+use keri::error::Error;
+use pkcs11::{types::*, Ctx};
+use ursa::keys::PublicKey;
+
+// Ed25519 key type and mechanisms of PKCS#11 v3.0, not known to the
+// bindings yet.
+const CKK_EC_EDWARDS: CK_KEY_TYPE = 0x40;
+const CKM_EC_EDWARDS_KEY_PAIR_GEN: CK_MECHANISM_TYPE = 0x1055;
+const CKM_EDDSA: CK_MECHANISM_TYPE = 0x1057;
+// DER encoded OID of edwards25519 curve: 1.3.6.1.4.1.11591.15.1.
+const ED25519_PARAMS: [u8; 11] = [
+    0x06, 0x09, 0x2b, 0x06, 0x01, 0x04, 0x01, 0xda, 0x47, 0x0f, 0x01,
+];
+// DER header of 32 bytes octet string wrapping Ed25519 public key.
+const EC_POINT_HEADER: [u8; 2] = [0x04, 0x20];
+
+struct Session {
+    ctx: Ctx,
+    handle: CK_SESSION_HANDLE,
+}
+
+// Logged in session with PKCS#11 token holding Ed25519 keys of TDA. Keys
+// are labeled `tda-<account>-<index>`.
+pub struct Token {
+    session: Mutex<Session>,
+}
+
+// Module is initialized by `Ctx` for multithreaded use and all calls go
+// through the mutex.
+unsafe impl Send for Token {}
+unsafe impl Sync for Token {}
+
+impl Token {
+    pub fn open(module: &Path, slot: CK_SLOT_ID, pin: &str) -> Result<Self, Error> {
+        let ctx = Ctx::new_and_initialize(module).map_err(token_error)?;
+        let handle = ctx
+            .open_session(slot, CKF_SERIAL_SESSION | CKF_RW_SESSION, None, None)
+            .map_err(token_error)?;
+        ctx.login(handle, CKU_USER, Some(pin))
+            .map_err(token_error)?;
+        Ok(Token {
+            session: Mutex::new(Session { ctx, handle }),
+        })
+    }
+
+    // Session is open and user is logged in, so the token can sign.
+    pub fn is_logged_in(&self) -> bool {
+        let session = self.session.lock().expect("PKCS#11 session poisoned");
+        match session.ctx.get_session_info(session.handle) {
+            Ok(info) => info.state == CKS_RO_USER_FUNCTIONS || info.state == CKS_RW_USER_FUNCTIONS,
+            Err(_) => false,
+        }
+    }
+
+    // Public key of given key, generated on the token if it's missing.
+    pub fn public_key(&self, account: u32, index: u32) -> Result<PublicKey, Error> {
+        let session = self.session.lock().expect("PKCS#11 session poisoned");
+        let label = format!("tda-{}-{}", account, index);
+        let key = match session.find(CKO_PUBLIC_KEY, CKA_LABEL, label.as_bytes())? {
+            Some(key) => key,
+            None => session.generate(&label)?,
+        };
+        let point = session.attribute(key, CKA_EC_POINT)?;
+        let public = if point.starts_with(&EC_POINT_HEADER) {
+            &point[EC_POINT_HEADER.len()..]
+        } else {
+            &point[..]
+        };
+        Ok(PublicKey(public.to_vec()))
+    }
+
+    pub fn sign(&self, public: &PublicKey, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let session = self.session.lock().expect("PKCS#11 session poisoned");
+        let point = [&EC_POINT_HEADER[..], &public.0].concat();
+        let unknown_key = || Error::SemanticError("key isn't held by PKCS#11 token".into());
+        // Point may be kept with or without its DER header, as in
+        // `public_key`.
+        let public_key = match session.find(CKO_PUBLIC_KEY, CKA_EC_POINT, &point)? {
+            Some(key) => key,
+            None => session
+                .find(CKO_PUBLIC_KEY, CKA_EC_POINT, &public.0)?
+                .ok_or_else(unknown_key)?,
+        };
+        let label = session.attribute(public_key, CKA_LABEL)?;
+        let private_key = session
+            .find(CKO_PRIVATE_KEY, CKA_LABEL, &label)?
+            .ok_or_else(unknown_key)?;
+        session
+            .ctx
+            .sign_init(session.handle, &mechanism(CKM_EDDSA), private_key)
+            .map_err(token_error)?;
+        session.ctx.sign(session.handle, data).map_err(token_error)
+    }
+}
+
+impl Session {
+    fn find(
+        &self,
+        class: CK_OBJECT_CLASS,
+        attribute: CK_ATTRIBUTE_TYPE,
+        value: &[u8],
+    ) -> Result<Option<CK_OBJECT_HANDLE>, Error> {
+        let template = vec![
+            CK_ATTRIBUTE::new(CKA_CLASS).with_ck_ulong(&class),
+            CK_ATTRIBUTE::new(attribute).with_bytes(value),
+        ];
+        self.ctx
+            .find_objects_init(self.handle, &template)
+            .map_err(token_error)?;
+        let found = self.ctx.find_objects(self.handle, 1).map_err(token_error);
+        self.ctx
+            .find_objects_final(self.handle)
+            .map_err(token_error)?;
+        Ok(found?.first().copied())
+    }
+
+    // Value of byte array attribute, read in two passes: length first.
+    fn attribute(
+        &self,
+        object: CK_OBJECT_HANDLE,
+        attribute: CK_ATTRIBUTE_TYPE,
+    ) -> Result<Vec<u8>, Error> {
+        let mut template = vec![CK_ATTRIBUTE::new(attribute)];
+        let (_, template) = self
+            .ctx
+            .get_attribute_value(self.handle, object, &mut template)
+            .map_err(token_error)?;
+        let value = vec![0; template[0].ulValueLen as usize];
+        let mut template = vec![CK_ATTRIBUTE::new(attribute).with_bytes(&value)];
+        self.ctx
+            .get_attribute_value(self.handle, object, &mut template)
+            .map_err(token_error)?;
+        Ok(value)
+    }
+
+    // Generate keypair which never leaves the token. Returns its public key.
+    fn generate(&self, label: &str) -> Result<CK_OBJECT_HANDLE, Error> {
+        let label = label.to_string();
+        let public_template = vec![
+            CK_ATTRIBUTE::new(CKA_KEY_TYPE).with_ck_ulong(&CKK_EC_EDWARDS),
+            CK_ATTRIBUTE::new(CKA_EC_PARAMS).with_bytes(&ED25519_PARAMS),
+            CK_ATTRIBUTE::new(CKA_LABEL).with_string(&label),
+            CK_ATTRIBUTE::new(CKA_TOKEN).with_bool(&CK_TRUE),
+            CK_ATTRIBUTE::new(CKA_VERIFY).with_bool(&CK_TRUE),
+        ];
+        let private_template = vec![
+            CK_ATTRIBUTE::new(CKA_KEY_TYPE).with_ck_ulong(&CKK_EC_EDWARDS),
+            CK_ATTRIBUTE::new(CKA_LABEL).with_string(&label),
+            CK_ATTRIBUTE::new(CKA_TOKEN).with_bool(&CK_TRUE),
+            CK_ATTRIBUTE::new(CKA_PRIVATE).with_bool(&CK_TRUE),
+            CK_ATTRIBUTE::new(CKA_SIGN).with_bool(&CK_TRUE),
+            CK_ATTRIBUTE::new(CKA_SENSITIVE).with_bool(&CK_TRUE),
+            CK_ATTRIBUTE::new(CKA_EXTRACTABLE).with_bool(&CK_FALSE),
+        ];
+        let (public, _) = self
+            .ctx
+            .generate_key_pair(
+                self.handle,
+                &mechanism(CKM_EC_EDWARDS_KEY_PAIR_GEN),
+                &public_template,
+                &private_template,
+            )
+            .map_err(token_error)?;
+        Ok(public)
+    }
+}
+
+fn mechanism(mechanism: CK_MECHANISM_TYPE) -> CK_MECHANISM {
+    CK_MECHANISM {
+        mechanism,
+        pParameter: ptr::null_mut(),
+        ulParameterLen: 0,
+    }
+}
+
+fn token_error(e: pkcs11::errors::Error) -> Error {
+    Error::SemanticError(format!("PKCS#11 token failed: {}", e))
+}
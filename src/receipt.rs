@@ -0,0 +1,143 @@
+//! Receipts of events, made by transferable or non-transferable
+//! identifiers.
+
+use keri::{
+    error::Error,
+    event::{event_data::EventData, EventMessage},
+    event_message::{parse, SignedEventMessage},
+    prefix::{BasicPrefix, Prefix, SelfSigningPrefix},
+};
+
+// Count code preceding couplets attached to non-transferable receipt.
+const COUPLETS_CODE: &str = "-C";
+// Lengths of Ed25519 basic prefix and signature prefix, as TDA supports only
+// Ed25519 keys.
+const BASIC_PREFIX_LEN: usize = 44;
+const SIGNATURE_PREFIX_LEN: usize = 88;
+const BASE64_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Receipt made by non-transferable identifier: `rct` event followed by
+/// couplets of signer's basic prefix and its signature.
+#[derive(Clone, Debug)]
+pub struct NontransferableReceipt {
+    /// The `rct` event.
+    pub body: EventMessage,
+    /// Basic prefixes of signers with their signatures of receipted event.
+    pub couplets: Vec<(BasicPrefix, SelfSigningPrefix)>,
+}
+
+impl NontransferableReceipt {
+    /// Event followed by counter and couplets, as sent over the wire.
+    pub fn serialize(&self) -> Result<Vec<u8>, Error> {
+        let mut out = self.body.serialize()?;
+        out.extend(COUPLETS_CODE.as_bytes());
+        out.extend(&encode_count(self.couplets.len()));
+        for (prefix, signature) in &self.couplets {
+            out.extend(prefix.to_str().as_bytes());
+            out.extend(signature.to_str().as_bytes());
+        }
+        Ok(out)
+    }
+}
+
+/// Receipt made by transferable (`vrc`) or non-transferable (`rct`)
+/// identifier.
+#[derive(Clone, Debug)]
+pub enum Receipt {
+    /// `vrc` event signed by validator.
+    Transferable(SignedEventMessage),
+    /// `rct` event with signatures of validators.
+    Nontransferable(NontransferableReceipt),
+}
+
+impl Receipt {
+    /// The receipt event, without signatures.
+    pub fn event_message(&self) -> &EventMessage {
+        match self {
+            Receipt::Transferable(vrc) => &vrc.event_message,
+            Receipt::Nontransferable(rct) => &rct.body,
+        }
+    }
+
+    /// Prefixes of validators which signed the receipt.
+    pub fn validators(&self) -> Vec<String> {
+        match self {
+            Receipt::Transferable(vrc) => match &vrc.event_message.event.event_data {
+                EventData::Vrc(rct) => vec![rct.validator_location_seal.prefix.to_str()],
+                _ => vec![],
+            },
+            Receipt::Nontransferable(rct) => rct
+                .couplets
+                .iter()
+                .map(|(validator, _)| validator.to_str())
+                .collect(),
+        }
+    }
+
+    /// Receipt with its signatures, as sent over the wire.
+    pub fn serialize(&self) -> Result<Vec<u8>, Error> {
+        match self {
+            Receipt::Transferable(vrc) => vrc.serialize(),
+            Receipt::Nontransferable(rct) => rct.serialize(),
+        }
+    }
+}
+
+/// Parse `rct` event with attached couplets. Returns `None` if message is
+/// anything else.
+pub fn parse_nontransferable(s: &str) -> Option<(&str, NontransferableReceipt)> {
+    let (rest, body) = parse::message(s).ok()?;
+    match body.event.event_data {
+        EventData::Rct(_) if rest.starts_with(COUPLETS_CODE) => (),
+        _ => return None,
+    }
+    let rest = &rest[COUPLETS_CODE.len()..];
+    let count = decode_count(rest.get(..2)?)?;
+    let mut rest = &rest[2..];
+    let mut couplets = vec![];
+    for _ in 0..count {
+        let prefix = rest.get(..BASIC_PREFIX_LEN)?.parse::<BasicPrefix>().ok()?;
+        rest = &rest[BASIC_PREFIX_LEN..];
+        let signature = rest
+            .get(..SIGNATURE_PREFIX_LEN)?
+            .parse::<SelfSigningPrefix>()
+            .ok()?;
+        rest = &rest[SIGNATURE_PREFIX_LEN..];
+        couplets.push((prefix, signature));
+    }
+    Some((rest, NontransferableReceipt { body, couplets }))
+}
+
+/// Parse stream of signed events which can contain non-transferable receipts,
+/// not understood by keri event stream parser.
+pub fn parse_stream(
+    stream: &str,
+) -> Result<(Vec<SignedEventMessage>, Vec<NontransferableReceipt>), Error> {
+    let mut events = vec![];
+    let mut receipts = vec![];
+    let mut rest = stream.trim_start();
+    while !rest.is_empty() {
+        if let Some((next, rct)) = parse_nontransferable(rest) {
+            receipts.push(rct);
+            rest = next.trim_start();
+            continue;
+        }
+        let (next, event) = parse::signed_message(rest)
+            .map_err(|_| Error::SemanticError("Can't parse event stream".into()))?;
+        events.push(event);
+        rest = next.trim_start();
+    }
+    Ok((events, receipts))
+}
+
+// Two base64 characters of count code.
+fn encode_count(count: usize) -> [u8; 2] {
+    [BASE64_CHARS[(count >> 6) & 63], BASE64_CHARS[count & 63]]
+}
+
+fn decode_count(code: &str) -> Option<usize> {
+    code.bytes().try_fold(0, |count, c| {
+        let digit = BASE64_CHARS.iter().position(|b| *b == c)?;
+        Some(count * 64 + digit)
+    })
+}
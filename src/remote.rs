@@ -0,0 +1,94 @@
+use keri::{
+    error::Error, event::event_data::EventData, event_message::SignedEventMessage,
+    state::IdentifierState,
+};
+
+use crate::{
+    log_state::{is_abandoned, recovery_base},
+    traits::{self, ConfigTrait},
+};
+
+// Known KEL of other identifier and its state.
+#[derive(Clone, Default)]
+pub struct RemoteLog {
+    pub kel: Vec<SignedEventMessage>,
+    pub state: IdentifierState,
+    // Interaction events superseded by recovery rotation.
+    pub superseded: Vec<SignedEventMessage>,
+}
+
+impl RemoteLog {
+    // Verify whole KEL from inception.
+    pub fn from_kel(kel: &[SignedEventMessage]) -> Result<Self, Error> {
+        let mut log = RemoteLog::default();
+        for event in kel {
+            log.apply(event)?;
+        }
+        Ok(log)
+    }
+
+    // Known KEL followed by events which resolved KEL adds after it. Resolved
+    // KEL must repeat the known one event for event, so stale or forged KEL
+    // served by introduction endpoint can't roll back rotation or abandonment
+    // of the identifier.
+    pub fn extended_by(&self, resolved: &RemoteLog) -> Result<RemoteLog, Error> {
+        if resolved.kel.len() < self.kel.len() {
+            return Err(Error::SemanticError(
+                "resolved KEL is shorter than the known one".into(),
+            ));
+        }
+        for (known, event) in self.kel.iter().zip(&resolved.kel) {
+            if known.event_message.serialize()? != event.event_message.serialize()? {
+                return Err(Error::SemanticError(format!(
+                    "resolved KEL diverges from the known one at sn {}",
+                    known.event_message.event.sn
+                )));
+            }
+        }
+        let mut log = self.clone();
+        for event in &resolved.kel[self.kel.len()..] {
+            log.apply(event)?;
+        }
+        Ok(log)
+    }
+
+    // Identifier committed to empty next key set and accepts no more events.
+    pub fn is_abandoned(&self) -> bool {
+        !self.kel.is_empty() && is_abandoned(&self.state)
+    }
+
+    // Configuration traits declared at inception.
+    pub fn traits(&self) -> Vec<ConfigTrait> {
+        traits::declared(&self.kel)
+    }
+
+    // Verify and apply next event of the identifier. Rotation at sn which is
+    // already in the KEL is accepted as recovery, if it only supersedes
+    // interaction events. Returns number of superseded events.
+    pub fn apply(&mut self, event: &SignedEventMessage) -> Result<usize, Error> {
+        if self.is_abandoned() {
+            return Err(Error::SemanticError("identifier is abandoned".into()));
+        }
+        traits::check(&self.traits(), event)?;
+        let sn = event.event_message.event.sn;
+        if self.kel.is_empty() || sn > self.state.sn {
+            self.state = self.state.clone().verify_and_apply(event)?;
+            self.kel.push(event.clone());
+            return Ok(0);
+        }
+        match event.event_message.event.event_data {
+            EventData::Rot(_) => {
+                self.state = recovery_base(&self.kel, sn)?.verify_and_apply(event)?;
+                let superseded: Vec<_> = self.kel.drain(sn as usize..).collect();
+                let count = superseded.len();
+                self.superseded.extend(superseded);
+                self.kel.push(event.clone());
+                Ok(count)
+            }
+            _ => Err(Error::SemanticError(format!(
+                "event at sn {} is already in KEL",
+                sn
+            ))),
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! Rotation history of local identifiers and policy rotating their keys.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::future;
+use keri::event_message::SignedEventMessage;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{actor::KeriHandle, send_event, send_receipt};
+
+/// Why establishment event was made.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    /// Identifier was incepted.
+    Inception,
+    /// Requested with `ROT` command.
+    Manual,
+    /// Requested with `REC` command, superseding interaction events.
+    Recovery,
+    /// Requested with `ABN` command, committing to empty next key set.
+    Abandonment,
+    /// Keys got older than `max_age` of rotation policy.
+    MaxAge,
+    /// Keys made `max_signatures` signatures.
+    MaxSignatures,
+}
+
+/// Establishment event made by the identifier.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Record {
+    /// Sequence number of the event.
+    pub sn: u64,
+    /// Why the event was made.
+    pub reason: Reason,
+    /// Unix time in seconds.
+    pub timestamp: u64,
+}
+
+/// Why and when establishment events of the identifier were made, and how
+/// many signatures current keys made since then.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct History {
+    /// Establishment events, oldest first.
+    pub records: Vec<Record>,
+    /// Signatures made by current keys.
+    pub signatures: u64,
+}
+
+impl History {
+    /// Record establishment event made now, resetting signature count.
+    pub fn record(&mut self, sn: u64, reason: Reason) {
+        self.records.push(Record {
+            sn,
+            reason,
+            timestamp: now(),
+        });
+        self.signatures = 0;
+    }
+
+    /// Count signature made by current keys.
+    pub fn signed(&mut self) {
+        self.signatures += 1;
+    }
+
+    /// Age of current keys, unknown if no establishment event was recorded,
+    /// e.g. for identifier restored from its KEL.
+    pub fn keys_age(&self) -> Option<Duration> {
+        self.records
+            .last()
+            .map(|r| Duration::from_secs(now().saturating_sub(r.timestamp)))
+    }
+}
+
+/// Rotate keys when they are older than `max_age` or made `max_signatures`
+/// signatures, whichever comes first.
+#[derive(Clone, Debug, Default)]
+pub struct Policy {
+    /// Longest time keys are used for.
+    pub max_age: Option<Duration>,
+    /// Most signatures keys make.
+    pub max_signatures: Option<u64>,
+}
+
+impl Policy {
+    /// Whether any limit is set, so keys are ever rotated.
+    pub fn is_enabled(&self) -> bool {
+        self.max_age.is_some() || self.max_signatures.is_some()
+    }
+
+    /// Returns reason of rotation if one is due.
+    pub fn due(&self, history: &History) -> Option<Reason> {
+        if let Some(max_age) = self.max_age {
+            // Keys of unknown age may be older than allowed.
+            if history.keys_age().map_or(true, |age| age >= max_age) {
+                return Some(Reason::MaxAge);
+            }
+        }
+        match self.max_signatures {
+            Some(max) if history.signatures >= max => Some(Reason::MaxSignatures),
+            _ => None,
+        }
+    }
+}
+
+/// Check rotation policy of every local identifier every `interval` and rotate
+/// its keys when it's due. New rotation events are sent to given peers to get
+/// their receipts.
+pub async fn schedule(keri: KeriHandle, policy: Policy, interval: Duration, peers: Vec<String>) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+
+        let policy = policy.clone();
+        let rotated = keri
+            .call(move |keri| {
+                let mut rotated = vec![];
+                for (prefix, id) in keri.ids.iter_mut() {
+                    // Such identifiers can't rotate keys anymore.
+                    if id.log.is_abandoned() || !id.log.is_transferable() {
+                        continue;
+                    }
+                    let reason = match policy.due(&id.rotations) {
+                        Some(reason) => reason,
+                        None => continue,
+                    };
+                    match id.rotate(reason) {
+                        Ok(rot) => {
+                            info!(prefix = %prefix, reason = ?reason, sn = rot.event_message.event.sn, "Keys rotated by policy");
+                            rotated.push((prefix.clone(), rot));
+                        }
+                        Err(e) => warn!(prefix = %prefix, reason = ?reason, "Can't rotate keys: {}", e),
+                    }
+                }
+                rotated
+            })
+            .await;
+
+        // Network round-trips are made between calls on the instance, on
+        // their own tasks, so unresponsive peer doesn't hold back later
+        // rotations.
+        for (prefix, rot) in rotated {
+            tokio::spawn(send_rotation(keri.clone(), prefix, rot, peers.clone()));
+        }
+    }
+}
+
+// Send rotation event to all peers at once, and receipts of events they
+// respond with back to them. Each exchange times out on its own, see
+// `send_event`.
+async fn send_rotation(
+    keri: KeriHandle,
+    prefix: String,
+    rot: SignedEventMessage,
+    peers: Vec<String>,
+) {
+    let exchanges = peers.into_iter().map(|peer| {
+        let (keri, prefix, rot) = (keri.clone(), prefix.clone(), rot.clone());
+        async move {
+            match send_event(peer.clone(), rot).await {
+                Ok((response, nontransferable)) => {
+                    let from = peer.clone();
+                    let receipts = keri
+                        .call(move |keri| {
+                            keri.process_response(&prefix, &from, response, nontransferable)
+                        })
+                        .await;
+                    for rct in receipts {
+                        if let Err(e) = send_receipt(peer.clone(), rct).await {
+                            warn!(peer = %peer, "Can't send receipt: {}", e);
+                        }
+                    }
+                }
+                Err(e) => warn!(peer = %peer, "Can't send rotation: {}", e),
+            }
+        }
+    });
+    future::join_all(exchanges).await;
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,53 @@
+use keri::{
+    derivation::self_addressing::SelfAddressing,
+    error::Error,
+    event::sections::seal::{DigestSeal, EventSeal, RootSeal, Seal},
+    prefix::{IdentifierPrefix, SelfAddressingPrefix},
+};
+
+const DIGEST: &str = "digest:";
+const ROOT: &str = "root:";
+const EVENT: &str = "event:";
+
+// Argument of `IXN` or `ROT` command describing a seal to anchor.
+pub fn is_seal(arg: &str) -> bool {
+    arg.starts_with(DIGEST) || arg.starts_with(ROOT) || arg.starts_with(EVENT)
+}
+
+// Parse seal given as command argument:
+// - `digest:<data>` - digest of given data, made with `derivation`,
+// - `root:<digest>` - root of merkle tree, as self-addressing prefix,
+// - `event:<prefix>:<digest>` - event of other identifier.
+pub fn parse(arg: &str, derivation: &SelfAddressing) -> Result<Seal, Error> {
+    if arg.starts_with(DIGEST) {
+        Ok(Seal::Digest(DigestSeal {
+            dig: derivation.derive(arg[DIGEST.len()..].as_bytes()),
+        }))
+    } else if arg.starts_with(ROOT) {
+        Ok(Seal::Root(RootSeal {
+            tree_root: parse_digest(&arg[ROOT.len()..])?,
+        }))
+    } else if arg.starts_with(EVENT) {
+        let mut parts = arg[EVENT.len()..].splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(prefix), Some(digest)) => Ok(Seal::Event(EventSeal {
+                prefix: prefix
+                    .parse::<IdentifierPrefix>()
+                    .map_err(|_| Error::SemanticError(format!("invalid prefix: {}", prefix)))?,
+                event_digest: parse_digest(digest)?,
+            })),
+            _ => Err(Error::SemanticError(format!(
+                "expected event:<prefix>:<digest>, got: {}",
+                arg
+            ))),
+        }
+    } else {
+        Err(Error::SemanticError(format!("unknown seal: {}", arg)))
+    }
+}
+
+fn parse_digest(digest: &str) -> Result<SelfAddressingPrefix, Error> {
+    digest
+        .parse::<SelfAddressingPrefix>()
+        .map_err(|_| Error::SemanticError(format!("invalid digest: {}", digest)))
+}
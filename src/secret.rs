@@ -0,0 +1,69 @@
+use std::{fmt, sync::Once};
+
+use tracing::warn;
+use zeroize::Zeroize;
+
+static LOCK_MEMORY: Once = Once::new();
+
+/// Lock all memory of the process, current and future, so secrets are never
+/// swapped to disk. Call it before any key material is loaded.
+//
+// Page locks aren't counted per secret, so the whole process is locked once
+// instead of each secret's pages, which unlocking one secret would unlock for
+// its neighbours too.
+pub fn lock_memory() {
+    LOCK_MEMORY.call_once(|| {
+        if let Err(e) = mlockall() {
+            warn!(
+                "Can't lock memory, key material may be swapped to disk: {}",
+                e
+            );
+        }
+    });
+}
+
+// Private key material. It's zeroized on drop, can't be cloned and isn't
+// shown in debug output. It's locked in memory with the rest of the process
+// if `lock_memory` was called.
+pub struct Secret {
+    bytes: Vec<u8>,
+}
+
+impl Secret {
+    // Take ownership of given bytes, without copying them.
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Secret { bytes }
+    }
+
+    pub fn expose(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.bytes.zeroize();
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Secret(..)")
+    }
+}
+
+#[cfg(unix)]
+fn mlockall() -> std::io::Result<()> {
+    match unsafe { libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE) } {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(unix))]
+fn mlockall() -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "locking memory isn't supported on this platform",
+    ))
+}
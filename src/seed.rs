@@ -0,0 +1,70 @@
+//! Mnemonic seeds keys of local identifiers are derived from.
+
+use std::{fs, path::Path, sync::Arc};
+
+use bip39::{Language, Mnemonic, MnemonicType};
+use hmac::{Hmac, Mac, NewMac};
+use keri::error::Error;
+use sha2::Sha512;
+use ursa::{
+    keys::{KeyGenOption, PrivateKey, PublicKey},
+    signatures::{ed25519::Ed25519Sha512, SignatureScheme},
+};
+use zeroize::Zeroize;
+
+use crate::secret::Secret;
+
+// Domain separating TDA key derivation from other uses of the same mnemonic.
+const DOMAIN: &[u8] = b"tda-standalone key derivation";
+
+/// Secret all keypairs of local identifiers are derived from, backed up as
+/// BIP-39 mnemonic phrase. Clones share one secret.
+#[derive(Clone)]
+pub struct Seed(Arc<Secret>);
+
+impl Seed {
+    /// New random seed with its 24 words mnemonic phrase.
+    pub fn generate() -> (String, Seed) {
+        let mnemonic = Mnemonic::new(MnemonicType::Words24, Language::English);
+        let seed = Seed::from_mnemonic(&mnemonic);
+        (mnemonic.into_phrase(), seed)
+    }
+
+    /// Seed of given mnemonic phrase.
+    pub fn from_phrase(phrase: &str) -> Result<Seed, Error> {
+        let mnemonic = Mnemonic::from_phrase(phrase.trim(), Language::English)
+            .map_err(|e| Error::SemanticError(format!("Invalid mnemonic: {}", e)))?;
+        Ok(Seed::from_mnemonic(&mnemonic))
+    }
+
+    /// Read mnemonic phrase from given file.
+    pub fn from_file(path: &Path) -> Result<Seed, Error> {
+        let mut phrase = fs::read_to_string(path)
+            .map_err(|e| Error::SemanticError(format!("Can't read {}: {}", path.display(), e)))?;
+        let seed = Seed::from_phrase(&phrase);
+        phrase.zeroize();
+        seed
+    }
+
+    fn from_mnemonic(mnemonic: &Mnemonic) -> Seed {
+        let seed = bip39::Seed::new(mnemonic, "");
+        Seed(Arc::new(Secret::new(seed.as_bytes().to_vec())))
+    }
+
+    /// Keypair number `index` of identifier number `account`. Keypairs of one
+    /// identifier form its rotation chain: index is the number of
+    /// establishment events made before the keys become current.
+    pub fn keypair(&self, account: u32, index: u32) -> Result<(PublicKey, PrivateKey), Error> {
+        let mut mac = Hmac::<Sha512>::new_varkey(DOMAIN)
+            .map_err(|_| Error::SemanticError("Can't derive key".into()))?;
+        mac.update(self.0.expose());
+        mac.update(&account.to_be_bytes());
+        mac.update(&index.to_be_bytes());
+        let mut child = mac.finalize().into_bytes();
+        let keypair = Ed25519Sha512::new()
+            .keypair(Some(KeyGenOption::UseSeed(child[..32].to_vec())))
+            .map_err(Error::CryptoError);
+        child.as_mut_slice().zeroize();
+        keypair
+    }
+}
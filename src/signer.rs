@@ -0,0 +1,203 @@
+//! Keypairs and external signers holding private keys outside of TDA.
+
+use std::path::{Path, PathBuf};
+#[cfg(feature = "pkcs11")]
+use std::sync::Arc;
+#[cfg(unix)]
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    time::Duration,
+};
+
+use keri::error::Error;
+use serde::{Deserialize, Serialize};
+use ursa::{
+    keys::{PrivateKey, PublicKey},
+    signatures::{ed25519::Ed25519Sha512, SignatureScheme},
+};
+use zeroize::Zeroize;
+
+use crate::secret::Secret;
+
+// How long to wait for the agent to respond.
+#[cfg(unix)]
+const AGENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Public key with its private key, unless that one is held by external
+/// signer, which is asked to sign by public key.
+pub struct Keypair {
+    /// Public key, current or committed to as next one.
+    pub public: PublicKey,
+    /// Private key, `None` if external signer holds it.
+    pub private: Option<Secret>,
+}
+
+impl Keypair {
+    /// Keypair generated by ursa, moving its private key into `Secret`.
+    pub fn local(keypair: (PublicKey, PrivateKey)) -> Self {
+        let (public, mut private) = keypair;
+        Keypair {
+            public,
+            private: Some(Secret::new(std::mem::take(&mut private.0))),
+        }
+    }
+
+    /// Keypair whose private key is held by external signer.
+    pub fn external(public: PublicKey) -> Self {
+        Keypair {
+            public,
+            private: None,
+        }
+    }
+
+    /// Sign data with private key, or ask signer holding it to.
+    pub fn sign(&self, data: &[u8], signer: Option<&Signer>) -> Result<Vec<u8>, Error> {
+        match (&self.private, signer) {
+            (Some(private), _) => {
+                // ursa needs the key as `PrivateKey`, so it's copied just for
+                // signing.
+                let mut private = PrivateKey(private.expose().to_vec());
+                let signature = Ed25519Sha512::new()
+                    .sign(data, &private)
+                    .map_err(Error::CryptoError);
+                private.0.zeroize();
+                signature
+            }
+            (None, Some(signer)) => signer.sign(&self.public, data),
+            (None, None) => Err(Error::SemanticError(
+                "private key is held by external signer, which isn't configured".into(),
+            )),
+        }
+    }
+}
+
+/// External signer holding private keys outside of TDA process. Keys are
+/// addressed like seeded ones, by account of the identifier and number of
+/// establishment events made before they become current.
+#[derive(Clone)]
+pub enum Signer {
+    /// Agent process listening on Unix socket.
+    Agent(PathBuf),
+    /// PKCS#11 token, e.g. HSM.
+    #[cfg(feature = "pkcs11")]
+    Pkcs11(Arc<crate::pkcs11::Token>),
+}
+
+// Request to the agent, sent as single line of JSON. Keys and data are
+// base64url encoded.
+#[derive(Serialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+enum AgentRequest<'a> {
+    PublicKey { account: u32, index: u32 },
+    Sign { public_key: &'a str, data: &'a str },
+}
+
+// Response of the agent, single line of JSON.
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum AgentResponse {
+    PublicKey(String),
+    Signature(String),
+    Error(String),
+}
+
+impl Signer {
+    /// Public key of given key, which signer generates if it doesn't hold it
+    /// yet.
+    pub fn public_key(&self, account: u32, index: u32) -> Result<PublicKey, Error> {
+        match self {
+            Signer::Agent(socket) => {
+                match agent_request(socket, &AgentRequest::PublicKey { account, index })? {
+                    AgentResponse::PublicKey(key) => Ok(PublicKey(decode(&key)?)),
+                    _ => Err(Error::SemanticError("Unexpected agent response".into())),
+                }
+            }
+            #[cfg(feature = "pkcs11")]
+            Signer::Pkcs11(token) => token.public_key(account, index),
+        }
+    }
+
+    /// Signer can be asked to sign: agent accepts connections, PKCS#11
+    /// session is still logged in.
+    pub fn is_available(&self) -> bool {
+        match self {
+            Signer::Agent(socket) => agent_accepts(socket),
+            #[cfg(feature = "pkcs11")]
+            Signer::Pkcs11(token) => token.is_logged_in(),
+        }
+    }
+
+    /// Sign data with private key of given public key.
+    pub fn sign(&self, public: &PublicKey, data: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            Signer::Agent(socket) => {
+                let request = AgentRequest::Sign {
+                    public_key: &encode(&public.0),
+                    data: &encode(data),
+                };
+                match agent_request(socket, &request)? {
+                    AgentResponse::Signature(signature) => decode(&signature),
+                    _ => Err(Error::SemanticError("Unexpected agent response".into())),
+                }
+            }
+            #[cfg(feature = "pkcs11")]
+            Signer::Pkcs11(token) => token.sign(public, data),
+        }
+    }
+}
+
+// Agent accepts connections on its socket.
+#[cfg(unix)]
+fn agent_accepts(socket: &Path) -> bool {
+    UnixStream::connect(socket).is_ok()
+}
+
+#[cfg(not(unix))]
+fn agent_accepts(_socket: &Path) -> bool {
+    false
+}
+
+// Send request to the agent over new connection and read its response.
+#[cfg(unix)]
+fn agent_request(socket: &Path, request: &AgentRequest) -> Result<AgentResponse, Error> {
+    let agent_error = |e: std::io::Error| {
+        Error::SemanticError(format!("Agent {} failed: {}", socket.display(), e))
+    };
+    let mut stream = UnixStream::connect(socket).map_err(agent_error)?;
+    stream
+        .set_read_timeout(Some(AGENT_TIMEOUT))
+        .map_err(agent_error)?;
+    let mut line = serde_json::to_vec(request)
+        .map_err(|e| Error::SemanticError(format!("Can't serialize request: {}", e)))?;
+    line.push(b'\n');
+    stream.write_all(&line).map_err(agent_error)?;
+
+    let mut response = String::new();
+    BufReader::new(stream)
+        .read_line(&mut response)
+        .map_err(agent_error)?;
+    match serde_json::from_str(&response)
+        .map_err(|e| Error::SemanticError(format!("Can't parse agent response: {}", e)))?
+    {
+        AgentResponse::Error(e) => Err(Error::SemanticError(format!("Agent refused: {}", e))),
+        response => Ok(response),
+    }
+}
+
+#[cfg(not(unix))]
+fn agent_request(socket: &Path, _request: &AgentRequest) -> Result<AgentResponse, Error> {
+    Err(Error::SemanticError(format!(
+        "Agent {} can't be reached, Unix sockets aren't supported on this platform",
+        socket.display()
+    )))
+}
+
+fn encode(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE)
+}
+
+fn decode(data: &str) -> Result<Vec<u8>, Error> {
+    base64::decode_config(data, base64::URL_SAFE)
+        .map_err(|e| Error::SemanticError(format!("Invalid agent encoding: {}", e)))
+}
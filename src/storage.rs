@@ -0,0 +1,258 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use keri::{error::Error, event_message::parse, event_message::SignedEventMessage};
+
+use crate::{
+    log_state::LogState,
+    receipt::{self, Receipt},
+    rotation::History,
+};
+
+const KEL_FILE: &str = "kel";
+const RECEIPTS_FILE: &str = "receipts";
+const ESCROW_FILE: &str = "escrow";
+const SUPERSEDED_FILE: &str = "superseded";
+const ROTATIONS_FILE: &str = "rotations.json";
+const KEYSTORE_FILE: &str = "keystore.json";
+const HOOKS_LOG_FILE: &str = "hooks.log";
+const NEXT_ACCOUNT_FILE: &str = "next_account";
+// Directory with storages of additional local identifiers, by prefix.
+const IDENTIFIERS_DIR: &str = "identifiers";
+
+// Events stored on disk, as needed to restore `LogState`.
+pub struct StoredLog {
+    pub kel: Vec<SignedEventMessage>,
+    // Transferable and non-transferable receipts.
+    pub receipts: Vec<Receipt>,
+    pub escrow: Vec<SignedEventMessage>,
+    pub superseded: Vec<SignedEventMessage>,
+}
+
+// Keeps KEL, receipts, escrowed receipts and superseded events of the
+// identifier in given directory, each as a stream of serialized events.
+#[derive(Clone)]
+pub struct Storage {
+    path: PathBuf,
+}
+
+impl Storage {
+    pub fn new(path: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&path)?;
+        Ok(Storage { path })
+    }
+
+    // Storage directory exists and is writable.
+    pub fn is_available(&self) -> bool {
+        fs::metadata(&self.path)
+            .map(|m| m.is_dir() && !m.permissions().readonly())
+            .unwrap_or(false)
+    }
+
+    // Storage of additional local identifier with given prefix.
+    pub fn identifier(&self, prefix: &str) -> io::Result<Storage> {
+        Storage::new(self.path.join(IDENTIFIERS_DIR).join(prefix))
+    }
+
+    // Prefixes of additional local identifiers kept in this storage.
+    pub fn identifiers(&self) -> io::Result<Vec<String>> {
+        let entries = match fs::read_dir(self.path.join(IDENTIFIERS_DIR)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        let mut prefixes = vec![];
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                prefixes.extend(entry.file_name().to_str().map(String::from));
+            }
+        }
+        prefixes.sort();
+        Ok(prefixes)
+    }
+
+    // Default keystore file of the identifier kept in this storage.
+    pub fn keystore_path(&self) -> PathBuf {
+        self.path.join(KEYSTORE_FILE)
+    }
+
+    // Log of hook deliveries, one JSON line per attempt.
+    pub fn hooks_log_path(&self) -> PathBuf {
+        self.path.join(HOOKS_LOG_FILE)
+    }
+
+    // Delete the directory with everything stored in it. KEL goes first, so
+    // the identifier isn't loaded again even if the rest can't be removed.
+    pub fn remove(&self) -> io::Result<()> {
+        match fs::remove_file(self.path.join(KEL_FILE)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => (),
+        }
+        fs::remove_dir_all(&self.path)
+    }
+
+    pub fn save(&self, log: &LogState) -> Result<(), Error> {
+        let mut receipts = vec![];
+        for rct in log.sigs_map.values().flatten() {
+            receipts.extend(rct.serialize()?);
+        }
+        self.write(KEL_FILE, log.log.iter())?;
+        self.write_stream(RECEIPTS_FILE, receipts)?;
+        self.write(ESCROW_FILE, log.escrow_sigs.iter())?;
+        self.write(SUPERSEDED_FILE, log.superseded.iter())
+    }
+
+    // Returns `None` if nothing was stored yet.
+    pub fn load(&self) -> Result<Option<StoredLog>, Error> {
+        let kel = self.read(KEL_FILE)?;
+        if kel.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(StoredLog {
+            kel,
+            receipts: self.read_receipts()?,
+            escrow: self.read(ESCROW_FILE)?,
+            superseded: self.read(SUPERSEDED_FILE)?,
+        }))
+    }
+
+    pub fn save_rotations(&self, history: &History) -> Result<(), Error> {
+        let content = serde_json::to_vec_pretty(history)
+            .map_err(|e| Error::SemanticError(format!("Can't serialize rotations: {}", e)))?;
+        write_atomic(&self.path.join(ROTATIONS_FILE), &content, false)
+            .map_err(|e| Error::SemanticError(format!("Can't write {}: {}", ROTATIONS_FILE, e)))
+    }
+
+    // Returns empty history if nothing was stored yet.
+    pub fn load_rotations(&self) -> Result<History, Error> {
+        match fs::read(self.path.join(ROTATIONS_FILE)) {
+            Ok(content) => serde_json::from_slice(&content).map_err(|e| {
+                Error::SemanticError(format!("Can't parse {}: {}", ROTATIONS_FILE, e))
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(History::default()),
+            Err(e) => Err(Error::SemanticError(format!(
+                "Can't read {}: {}",
+                ROTATIONS_FILE, e
+            ))),
+        }
+    }
+
+    // Account of the next incepted identifier, see `KeriInstance::incept`.
+    pub fn save_next_account(&self, account: u32) -> Result<(), Error> {
+        write_atomic(
+            &self.path.join(NEXT_ACCOUNT_FILE),
+            account.to_string().as_bytes(),
+            false,
+        )
+        .map_err(|e| Error::SemanticError(format!("Can't write {}: {}", NEXT_ACCOUNT_FILE, e)))
+    }
+
+    // Returns `None` if nothing was stored yet.
+    pub fn load_next_account(&self) -> Result<Option<u32>, Error> {
+        let content = self.read_stream(NEXT_ACCOUNT_FILE)?;
+        if content.is_empty() {
+            return Ok(None);
+        }
+        content
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|e| Error::SemanticError(format!("Can't parse {}: {}", NEXT_ACCOUNT_FILE, e)))
+    }
+
+    fn write<'a>(
+        &self,
+        file: &str,
+        events: impl Iterator<Item = &'a SignedEventMessage>,
+    ) -> Result<(), Error> {
+        let mut stream = vec![];
+        for event in events {
+            stream.extend(event.serialize()?);
+        }
+        self.write_stream(file, stream)
+    }
+
+    fn write_stream(&self, file: &str, stream: Vec<u8>) -> Result<(), Error> {
+        write_atomic(&self.path.join(file), &stream, false)
+            .map_err(|e| Error::SemanticError(format!("Can't write {}: {}", file, e)))
+    }
+
+    fn read(&self, file: &str) -> Result<Vec<SignedEventMessage>, Error> {
+        let stream = self.read_stream(file)?;
+        if stream.is_empty() {
+            return Ok(vec![]);
+        }
+        parse::signed_event_stream(&stream)
+            .map(|(_, events)| events)
+            .map_err(|_| Error::SemanticError(format!("Can't parse {}", file)))
+    }
+
+    // Receipts file mixes transferable receipts, which are signed events, and
+    // non-transferable ones with attached couplets.
+    fn read_receipts(&self) -> Result<Vec<Receipt>, Error> {
+        let stream = self.read_stream(RECEIPTS_FILE)?;
+        let (vrcs, rcts) = receipt::parse_stream(&stream)
+            .map_err(|_| Error::SemanticError(format!("Can't parse {}", RECEIPTS_FILE)))?;
+        Ok(vrcs
+            .into_iter()
+            .map(Receipt::Transferable)
+            .chain(rcts.into_iter().map(Receipt::Nontransferable))
+            .collect())
+    }
+
+    // Returns empty string if file doesn't exist.
+    fn read_stream(&self, file: &str) -> Result<String, Error> {
+        let stream = match fs::read(self.path.join(file)) {
+            Ok(stream) => stream,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(String::new()),
+            Err(e) => return Err(Error::SemanticError(format!("Can't read {}: {}", file, e))),
+        };
+        String::from_utf8(stream)
+            .map_err(|_| Error::SemanticError(format!("{} is not valid utf8", file)))
+    }
+}
+
+// Replace content of the file: it's written to temporary file next to it,
+// synced and renamed over it, so whenever writing stops, the file has either
+// its old content or the new one. With `private`, the file is readable only
+// by its owner.
+pub fn write_atomic(path: &Path, content: &[u8], private: bool) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    // Leftover of interrupted write may have other permissions.
+    match fs::remove_file(&tmp) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        if private {
+            options.mode(0o600);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    let mut file = options.open(&tmp)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp, path)?;
+    // Rename itself is durable once its directory is synced.
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
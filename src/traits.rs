@@ -0,0 +1,69 @@
+//! Configuration traits declared in inception events.
+
+use keri::{
+    error::Error, event::event_data::EventData, event_message::SignedEventMessage,
+    prefix::IdentifierPrefix,
+};
+
+/// Configuration traits declared in inception event, restricting what events
+/// the identifier can make.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConfigTrait {
+    /// Only establishment events, no interaction events.
+    EstablishmentOnly,
+    /// Identifier can't act as delegator.
+    DoNotDelegate,
+}
+
+impl ConfigTrait {
+    /// Code of the trait in inception event, e.g. `EO`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ConfigTrait::EstablishmentOnly => "EO",
+            ConfigTrait::DoNotDelegate => "DND",
+        }
+    }
+
+    /// Trait with given code, `None` for unknown ones.
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "EO" => Some(ConfigTrait::EstablishmentOnly),
+            "DND" => Some(ConfigTrait::DoNotDelegate),
+            _ => None,
+        }
+    }
+}
+
+/// Traits declared in inception event of given KEL. Unknown codes are
+/// ignored.
+pub fn declared(kel: &[SignedEventMessage]) -> Vec<ConfigTrait> {
+    match kel.first().map(|icp| &icp.event_message.event.event_data) {
+        Some(EventData::Icp(icp)) => icp
+            .inception_configuration
+            .iter()
+            .filter_map(|code| ConfigTrait::from_code(code))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Check that identifier with given traits is allowed to make the event.
+pub fn check(traits: &[ConfigTrait], event: &SignedEventMessage) -> Result<(), Error> {
+    match event.event_message.event.event_data {
+        EventData::Ixn(_) if traits.contains(&ConfigTrait::EstablishmentOnly) => {
+            Err(Error::SemanticError(
+                "establishment-only identifier can't make interaction events".into(),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Prefix of identifier which delegated given event, if it's a delegated one.
+pub fn delegator(event: &SignedEventMessage) -> Option<&IdentifierPrefix> {
+    match &event.event_message.event.event_data {
+        EventData::Dip(dip) => Some(&dip.seal.prefix),
+        EventData::Drt(drt) => Some(&drt.seal.prefix),
+        _ => None,
+    }
+}
@@ -0,0 +1,191 @@
+// Harness running several TDA instances in-process, each listening on
+// ephemeral localhost port, with commands executed directly on them.
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    net::SocketAddr,
+    path::PathBuf,
+    process,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use keri::{event_message::SignedEventMessage, state::IdentifierState};
+use tokio::net::TcpListener;
+
+use tda_standalone::{
+    actor::KeriHandle,
+    config::Config,
+    control::{self, Session},
+    identifier::Identifier,
+    metrics::{ConnectionGuard, Metrics},
+    receipt::Receipt,
+    KeriInstance, Recovery,
+};
+
+// How long to wait for other node to process what was sent to it.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Node {
+    pub keri: KeriHandle,
+    pub address: SocketAddr,
+    pub prefix: String,
+    session: Session,
+}
+
+impl Node {
+    // Incept new identifier, without storage, and accept connections of
+    // other nodes.
+    pub async fn start() -> Node {
+        Node::with_config(Config::default()).await
+    }
+
+    pub async fn with_config(config: Config) -> Node {
+        let metrics = Arc::new(Metrics::default());
+        let keri = KeriInstance::from_config(&config, None, Arc::clone(&metrics))
+            .expect("Can't incept identifier");
+        Node::serve(keri, metrics).await
+    }
+
+    // Restore primary identifier from its KEL, as `--recover-from` does.
+    pub async fn recovered(config: Config, recovery: Recovery) -> Node {
+        let metrics = Arc::new(Metrics::default());
+        let keri = KeriInstance::from_config(&config, Some(recovery), Arc::clone(&metrics))
+            .expect("Can't recover identifier");
+        Node::serve(keri, metrics).await
+    }
+
+    async fn serve(keri: KeriInstance, metrics: Arc<Metrics>) -> Node {
+        let prefix = keri.primary().to_string();
+        let keri = KeriHandle::spawn(keri);
+
+        let mut listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Can't bind listener");
+        let address = listener.local_addr().expect("Can't get local address");
+        let (accepting, primary) = (keri.clone(), prefix.clone());
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(_) => return,
+                };
+                let session = Session::new(primary.clone(), "127.0.0.1:0".into(), true);
+                let connection = ConnectionGuard::new(Arc::clone(&metrics));
+                tokio::spawn(control::handle_connection(
+                    socket,
+                    accepting.clone(),
+                    session,
+                    connection,
+                ));
+            }
+        });
+
+        let session = Session::new(prefix.clone(), "127.0.0.1:0".into(), true);
+        Node {
+            keri,
+            address,
+            prefix,
+            session,
+        }
+    }
+
+    // Execute control command as if it was sent over connection, newline
+    // terminated like from netcat, and return the response.
+    pub async fn command(&mut self, command: &str) -> String {
+        let command = format!("{}\n", command);
+        let response = control::execute(&self.keri, &mut self.session, &command).await;
+        String::from_utf8(response).expect("Response isn't utf8")
+    }
+
+    // Process receipt as if other node sent it over connection. Returns once
+    // it's processed, with the response.
+    pub async fn accept(&mut self, rct: Receipt) -> String {
+        let msg = rct.serialize().expect("Can't serialize receipt");
+        let msg = String::from_utf8(msg).expect("Receipt isn't utf8");
+        let response = control::execute(&self.keri, &mut self.session, &msg).await;
+        String::from_utf8(response).expect("Response isn't utf8")
+    }
+
+    // Send last event of the node to other one with `SEN`.
+    pub async fn send_to(&mut self, other: &Node) -> String {
+        let command = format!("SEN {} {}", other.address.ip(), other.address.port());
+        self.command(&command).await
+    }
+
+    // Apply `f` to node's own identifier.
+    pub async fn own<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&Identifier) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let prefix = self.prefix.clone();
+        self.keri
+            .call(move |keri| f(keri.id(&prefix).unwrap()))
+            .await
+    }
+
+    pub async fn kel(&self) -> Vec<SignedEventMessage> {
+        self.own(|id| id.log.log.clone()).await
+    }
+
+    pub async fn sn(&self) -> u64 {
+        self.own(|id| id.log.state.sn).await
+    }
+
+    // Number of receipts of own event with given sn.
+    pub async fn receipts(&self, sn: u64) -> usize {
+        self.own(move |id| id.log.receipts(sn)).await
+    }
+
+    pub async fn escrowed(&self) -> usize {
+        self.own(|id| id.log.escrow_sigs.len()).await
+    }
+
+    // Process KEL of other node, as if it was resolved from its introduction.
+    pub async fn learn(&self, other: &Node) {
+        let (prefix, kel) = (other.prefix.clone(), other.kel().await);
+        self.keri
+            .call(move |keri| keri.process_kel(&prefix, &kel))
+            .await
+            .expect("Can't process KEL");
+    }
+
+    // Receipt of other node's event made by this node.
+    pub async fn receipt(&self, event: &SignedEventMessage) -> Receipt {
+        let (prefix, event) = (self.prefix.clone(), event.event_message.clone());
+        self.keri
+            .call(move |keri| keri.id_mut(&prefix).and_then(|id| id.make_rct(event)))
+            .await
+            .expect("Can't make receipt")
+    }
+
+    // State of other node's identifier as known to this node.
+    pub async fn remote_state(&self, other: &Node) -> IdentifierState {
+        let prefix = other.prefix.clone();
+        self.keri.call(move |keri| keri.remote_state(&prefix)).await
+    }
+}
+
+// Fresh directory for storage of one test.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tda-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("Can't create temporary directory");
+    dir
+}
+
+// Wait until the condition holds, as other nodes process what was sent to
+// them on their own tasks.
+pub async fn eventually<F, Fut>(mut condition: F)
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = bool>,
+{
+    let start = Instant::now();
+    while !condition().await {
+        assert!(start.elapsed() < TIMEOUT, "condition not met in time");
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+    }
+}
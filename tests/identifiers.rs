@@ -0,0 +1,348 @@
+mod common;
+
+#[cfg(unix)]
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixListener,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+use std::{fs, path::Path, sync::Arc, time::Duration};
+
+use common::{temp_dir, Node};
+#[cfg(unix)]
+use serde_json::json;
+use tda_standalone::{
+    config::{Config, ConfigError},
+    metrics::Metrics,
+    rotation::{Policy, Reason},
+    KeriInstance, Recovery,
+};
+#[cfg(unix)]
+use tda_standalone::{seed::Seed, signer::Keypair};
+
+const PHRASE: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon \
+                      abandon abandon about";
+const OTHER_PHRASE: &str =
+    "legal winner thank year wave sausage worth useful legal winner thank yellow";
+
+// Config with keys derived from the phrase, kept in given directory.
+fn seeded(dir: &Path) -> Config {
+    let mnemonic = dir.join("mnemonic");
+    fs::write(&mnemonic, PHRASE).expect("Can't write mnemonic");
+    let mut config = Config::default();
+    config.storage.path = Some(dir.join("storage"));
+    config.identifier.mnemonic_file = Some(mnemonic);
+    config
+}
+
+fn incepted(response: String) -> String {
+    response
+        .strip_prefix("Incepted ")
+        .map(|prefix| prefix.trim().to_string())
+        .unwrap_or_else(|| panic!("Unexpected response: {}", response))
+}
+
+#[tokio::test]
+async fn deleted_account_is_not_reused() {
+    let dir = temp_dir("accounts");
+    let mut node = Node::with_config(seeded(&dir)).await;
+
+    let first = incepted(node.command("NEW").await);
+    assert_eq!(
+        node.command(&format!("DEL {}", first)).await,
+        format!("Deleted {}\n", first)
+    );
+    let second = incepted(node.command("NEW").await);
+    assert_ne!(first, second);
+    drop(node);
+
+    // Counter survives restart, even after the last identifier is deleted.
+    let mut node = Node::with_config(seeded(&dir)).await;
+    assert_eq!(
+        node.command(&format!("DEL {}", second)).await,
+        format!("Deleted {}\n", second)
+    );
+    let third = incepted(node.command("NEW").await);
+    assert_ne!(third, first);
+    assert_ne!(third, second);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn restored_identifier_is_due_for_rotation_by_age() {
+    let dir = temp_dir("restored-age");
+    let mut original = Node::with_config(seeded(&dir)).await;
+    assert_eq!(original.command("ROT").await, "");
+
+    // Machine is lost, identifier is restored from its KEL and mnemonic.
+    let mut config = seeded(&dir);
+    config.storage.path = Some(dir.join("restored"));
+    let recovery = Recovery {
+        kel: original.kel().await,
+        next_account: 1,
+    };
+    let restored = Node::recovered(config, recovery).await;
+    assert_eq!(restored.prefix, original.prefix);
+    assert_eq!(restored.sn().await, 1);
+
+    // Nothing tells how old restored keys are, so they're rotated.
+    let policy = Policy {
+        max_age: Some(Duration::from_secs(24 * 60 * 60)),
+        max_signatures: None,
+    };
+    let due = policy.clone();
+    assert_eq!(
+        restored.own(move |id| due.due(&id.rotations)).await,
+        Some(Reason::MaxAge)
+    );
+    assert_eq!(
+        original.own(move |id| policy.due(&id.rotations)).await,
+        None
+    );
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn restored_identifier_continues_its_kel() {
+    let dir = temp_dir("restored-kel");
+    let mut original = Node::with_config(seeded(&dir)).await;
+    assert_eq!(original.command("ROT").await, "");
+    assert_eq!(original.command("IXN hello").await, "");
+    assert_eq!(original.command("ROT").await, "");
+    let kel = original.kel().await;
+
+    // Keys derived from other mnemonic aren't the ones KEL commits to.
+    let other = dir.join("other-mnemonic");
+    fs::write(&other, OTHER_PHRASE).expect("Can't write mnemonic");
+    let mut config = seeded(&dir);
+    config.storage.path = Some(dir.join("other"));
+    config.identifier.mnemonic_file = Some(other);
+    let recovery = Recovery {
+        kel: kel.clone(),
+        next_account: 1,
+    };
+    let metrics = Arc::new(Metrics::default());
+    assert!(KeriInstance::from_config(&config, Some(recovery), metrics).is_err());
+
+    let mut config = seeded(&dir);
+    config.storage.path = Some(dir.join("restored"));
+    let recovery = Recovery {
+        kel,
+        next_account: 1,
+    };
+    let mut restored = Node::recovered(config, recovery).await;
+    assert_eq!(restored.sn().await, 3);
+    assert_eq!(restored.command("ROT").await, "");
+    assert_eq!(restored.command("IXN after restore").await, "");
+    assert!(restored.own(|id| id.log.verify_kel()).await);
+
+    // Peer which knew the KEL before the machine was lost accepts events
+    // made after restoring it.
+    let peer = Node::start().await;
+    peer.learn(&original).await;
+    peer.learn(&restored).await;
+    assert_eq!(peer.remote_state(&restored).await.sn, 5);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn recovery_skips_accounts_of_lost_machine() {
+    let dir = temp_dir("recovered-accounts");
+    let mut lost = Node::with_config(seeded(&dir)).await;
+    let made = incepted(lost.command("NEW").await);
+
+    let mut config = seeded(&dir);
+    config.storage.path = Some(dir.join("recovered"));
+    let recovery = Recovery {
+        kel: lost.kel().await,
+        next_account: 2,
+    };
+    let mut recovered = Node::recovered(config, recovery).await;
+    assert_eq!(recovered.prefix, lost.prefix);
+    // Keys derived again sign new events of the primary identifier.
+    assert_eq!(recovered.command("IXN after recovery").await, "");
+    assert!(recovered.own(|id| id.log.verify_kel()).await);
+
+    // Identifier made on the lost machine isn't incepted again.
+    let new = incepted(recovered.command("NEW").await);
+    assert_ne!(new, made);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+// Agent holding keys derived from the phrase, serving requests of TDA
+// configured with `signer.kind = "agent"`. Returns number of requests it
+// served.
+#[cfg(unix)]
+fn agent(socket: &Path) -> Arc<AtomicUsize> {
+    let listener = UnixListener::bind(socket).expect("Can't bind agent socket");
+    let seed = Seed::from_phrase(PHRASE).expect("Invalid mnemonic");
+    let served = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&served);
+    thread::spawn(move || {
+        // Keys TDA asked for, by public key.
+        let mut keys = HashMap::new();
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => return,
+            };
+            let mut line = String::new();
+            if BufReader::new(&stream).read_line(&mut line).is_err() {
+                continue;
+            }
+            let request: serde_json::Value = serde_json::from_str(&line).expect("Invalid request");
+            let response = match request["method"].as_str() {
+                Some("public_key") => {
+                    let account = request["account"].as_u64().unwrap() as u32;
+                    let index = request["index"].as_u64().unwrap() as u32;
+                    let keypair = Keypair::local(seed.keypair(account, index).unwrap());
+                    let public = base64::encode_config(&keypair.public.0, base64::URL_SAFE);
+                    keys.insert(public.clone(), keypair);
+                    json!({ "public_key": public })
+                }
+                Some("sign") => {
+                    let public = request["public_key"].as_str().unwrap();
+                    let data = request["data"].as_str().unwrap();
+                    let data = base64::decode_config(data, base64::URL_SAFE).unwrap();
+                    match keys.get(public) {
+                        Some(keypair) => {
+                            let signature = keypair.sign(&data, None).unwrap();
+                            let signature = base64::encode_config(&signature, base64::URL_SAFE);
+                            json!({ "signature": signature })
+                        }
+                        None => json!({ "error": "unknown key" }),
+                    }
+                }
+                _ => json!({ "error": "unknown method" }),
+            };
+            counter.fetch_add(1, Ordering::SeqCst);
+            let _ = writeln!(stream, "{}", response);
+        }
+    });
+    served
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn agent_signs_for_tda() {
+    let dir = temp_dir("agent");
+    let socket = dir.join("agent.sock");
+    let served = agent(&socket);
+    let mut config = Config::default();
+    config.signer.kind = "agent".into();
+    config.signer.socket = Some(socket);
+    let mut a = Node::with_config(config).await;
+    assert!(served.load(Ordering::SeqCst) > 0);
+
+    // Agent's keys are the ones derived from the phrase for the first
+    // account, but private keys never enter TDA.
+    let seeded = Node::with_config(seeded(&dir)).await;
+    assert_eq!(a.prefix, seeded.prefix);
+    assert!(
+        a.own(|id| id.log.keys.iter().all(|key| key.keypair.private.is_none()))
+            .await
+    );
+
+    let before = served.load(Ordering::SeqCst);
+    assert_eq!(a.command("ROT").await, "");
+    assert_eq!(a.command("IXN signed by agent").await, "");
+    assert!(served.load(Ordering::SeqCst) > before);
+    assert!(a.own(|id| id.log.verify_kel()).await);
+
+    let peer = Node::start().await;
+    peer.learn(&a).await;
+    assert_eq!(peer.remote_state(&a).await.sn, 2);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn invalid_config_is_rejected() {
+    let invalid: Vec<(fn(&mut Config), &str)> = vec![
+        (
+            |config| config.listen.http_port = config.listen.port,
+            "listen.port and listen.http_port must differ",
+        ),
+        (
+            |config| config.listen.control_socket = None,
+            "listen.control_socket or listen.tcp_control is needed to control TDA",
+        ),
+        (
+            |config| config.storage.keystore = Some("keystore.json".into()),
+            "storage.keystore requires storage.path to be set",
+        ),
+        (
+            |config| config.identifier.traits = vec!["XX".into()],
+            "unknown identifier trait: XX, supported: EO, DND",
+        ),
+        (
+            |config| {
+                config.identifier.transferable = false;
+                config.rotation.max_age_days = Some(30);
+            },
+            "non-transferable identifier can't rotate keys, remove rotation policy",
+        ),
+        (
+            |config| config.signer.kind = "agent".into(),
+            "agent signer requires signer.socket",
+        ),
+        (
+            |config| {
+                config.signer.kind = "agent".into();
+                config.signer.socket = Some("agent.sock".into());
+                config.identifier.mnemonic_file = Some("mnemonic".into());
+            },
+            "keys can't be both derived from mnemonic and held by external signer",
+        ),
+        (
+            |config| config.rotation.max_signatures = Some(0),
+            "rotation.max_age_days and rotation.max_signatures must be positive",
+        ),
+        (
+            |config| config.log.level = "loud".into(),
+            "unknown log.level: loud, expected one of: error, warn, info, debug, trace",
+        ),
+    ];
+    assert!(Config::default().validate().is_ok());
+    for (change, error) in invalid {
+        let mut config = Config::default();
+        change(&mut config);
+        match config.validate() {
+            Err(e) => assert_eq!(e.to_string(), error),
+            Ok(()) => panic!("Config accepted, expected: {}", error),
+        }
+    }
+}
+
+#[test]
+fn config_file_is_parsed_strictly() {
+    let example = Config::from_file(Path::new("tda.example.toml")).expect("Can't parse example");
+    assert!(example.validate().is_ok());
+
+    let dir = temp_dir("config");
+    let path = dir.join("tda.toml");
+    fs::write(&path, "[listen]\nport = 1\nhttp_port = 1\n").unwrap();
+    let config = Config::from_file(&path).expect("Can't parse config");
+    assert!(config.validate().is_err());
+
+    // Misplaced key is an error rather than silently ignored.
+    fs::write(&path, "[identifier]\nwitnesses = []\n").unwrap();
+    assert!(matches!(
+        Config::from_file(&path),
+        Err(ConfigError::Parse(..))
+    ));
+    fs::write(&path, "[[hooks]]\non = \"event\"\n").unwrap();
+    let config = Config::from_file(&path).expect("Can't parse config");
+    assert_eq!(
+        config.validate().unwrap_err().to_string(),
+        "hook requires either url or non-empty command"
+    );
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,230 @@
+// Random sequences of events and receipts applied to LogState, checking its
+// invariants after every step.
+use std::collections::HashSet;
+
+use keri::{
+    derivation::self_addressing::SelfAddressing, event::event_data::EventData,
+    event::sections::seal::EventSeal, event_message::SignedEventMessage, state::IdentifierState,
+};
+use proptest::prelude::*;
+
+use tda_standalone::{
+    log_state::{KeyGen, LogState},
+    receipt::Receipt,
+};
+
+#[derive(Clone, Debug)]
+enum Op {
+    // Controller makes event.
+    Rotate,
+    Interact(String),
+    // Validator makes event, so receipts sealed with its previous one are
+    // escrowed.
+    ValidatorInteract,
+    // Validator receipts controller's event, picked by index into its KEL.
+    // Rejected if validator already receipted it.
+    Receipt(usize),
+    // Receipt which must not land in sigs_map.
+    Forged(usize, Forgery),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Forgery {
+    // Signed by other identifier, sealed with validator's last event.
+    OtherSigner,
+    // Validator's receipt of one event, claimed to receipt another one.
+    WrongEvent,
+    // Validator's receipt made before its latest event.
+    Stale,
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        Just(Op::Rotate),
+        "[a-z]{1,16}".prop_map(Op::Interact),
+        Just(Op::ValidatorInteract),
+        any::<usize>().prop_map(Op::Receipt),
+        (any::<usize>(), forgery()).prop_map(|(i, forgery)| Op::Forged(i, forgery)),
+    ]
+}
+
+fn forgery() -> impl Strategy<Value = Forgery> {
+    prop_oneof![
+        Just(Forgery::OtherSigner),
+        Just(Forgery::WrongEvent),
+        Just(Forgery::Stale),
+    ]
+}
+
+struct Model {
+    controller: LogState,
+    validator: LogState,
+    other: LogState,
+    // Every state validator went through, receipts sealed with any of them
+    // verify.
+    validator_states: Vec<IdentifierState>,
+    // Number of receipts expected in controller's sigs_map.
+    stored: usize,
+    // Sns of controller's events validator receipted.
+    receipted: HashSet<u64>,
+}
+
+impl Model {
+    fn new() -> Model {
+        let incept = || {
+            LogState::new(
+                SelfAddressing::Blake3_256,
+                vec![],
+                None,
+                &[],
+                KeyGen::Random,
+            )
+            .expect("Can't incept identifier")
+        };
+        let validator = incept();
+        Model {
+            controller: incept(),
+            validator_states: vec![validator.state.clone()],
+            validator,
+            other: incept(),
+            stored: 0,
+            receipted: HashSet::new(),
+        }
+    }
+
+    fn apply(&mut self, op: Op) {
+        match op {
+            Op::Rotate => {
+                self.controller.rotate().expect("Can't rotate");
+            }
+            Op::Interact(payload) => {
+                self.controller
+                    .make_ixn(&payload)
+                    .expect("Can't make interaction");
+            }
+            Op::ValidatorInteract => self.validator_interact(),
+            Op::Receipt(i) => {
+                let rct = receipt(&self.validator, self.event(i));
+                let sn = rct.event_message.event.sn;
+                let added = self.controller.add_sig(&self.validator.state, rct);
+                if self.receipted.insert(sn) {
+                    added.expect("Valid receipt rejected");
+                    self.stored += 1;
+                } else {
+                    assert!(added.is_err(), "Duplicate receipt accepted");
+                }
+            }
+            Op::Forged(i, forgery) => {
+                let rct = self.forge(i, forgery);
+                let sn = rct.event_message.event.sn;
+                let escrowed = self.controller.escrow_sigs.len();
+                let added = self.controller.add_sig(&self.validator.state, rct);
+                match forgery {
+                    Forgery::Stale if !self.receipted.contains(&sn) => {
+                        assert!(added.is_ok());
+                        assert_eq!(self.controller.escrow_sigs.len(), escrowed + 1);
+                    }
+                    _ => assert!(added.is_err(), "Forged receipt accepted"),
+                }
+            }
+        }
+    }
+
+    fn validator_interact(&mut self) {
+        self.validator
+            .make_ixn("validator")
+            .expect("Can't make interaction");
+        self.validator_states.push(self.validator.state.clone());
+    }
+
+    fn event(&self, i: usize) -> &SignedEventMessage {
+        &self.controller.log[i % self.controller.log.len()]
+    }
+
+    fn forge(&mut self, i: usize, forgery: Forgery) -> SignedEventMessage {
+        match forgery {
+            Forgery::OtherSigner => {
+                let mut rct = receipt(&self.other, self.event(i));
+                if let EventData::Vrc(vrc) = &mut rct.event_message.event.event_data {
+                    vrc.validator_location_seal = EventSeal {
+                        prefix: self.validator.state.prefix.clone(),
+                        event_digest: self.validator.derivation.derive(&self.validator.state.last),
+                    };
+                }
+                rct
+            }
+            Forgery::WrongEvent => {
+                let mut rct = receipt(&self.validator, self.event(i));
+                let sn = rct.event_message.event.sn;
+                // Either other event or one controller hasn't made yet.
+                rct.event_message.event.sn = if sn > 0 { sn - 1 } else { sn + 1 };
+                rct
+            }
+            Forgery::Stale => {
+                let rct = receipt(&self.validator, self.event(i));
+                self.validator_interact();
+                rct
+            }
+        }
+    }
+
+    fn check(&self) {
+        let log = &self.controller;
+        assert!(log.verify_kel(), "KEL doesn't verify from inception");
+        assert_eq!(log.state.sn as usize, log.log.len() - 1);
+
+        let stored: usize = log.sigs_map.values().map(Vec::len).sum();
+        assert_eq!(stored, self.stored, "Unexpected receipts in sigs_map");
+        for (sn, receipts) in &log.sigs_map {
+            assert_eq!(log.receipts(*sn), receipts.len(), "Duplicate receipts");
+            let event = &log.log[*sn as usize];
+            for rct in receipts {
+                assert!(self.verifies(event, rct), "Receipt doesn't verify");
+            }
+        }
+    }
+
+    // Receipt is bound to the event and signed by validator's keys of the
+    // state it's sealed with.
+    fn verifies(&self, event: &SignedEventMessage, rct: &Receipt) -> bool {
+        let vrc = match rct {
+            Receipt::Transferable(vrc) => vrc,
+            Receipt::Nontransferable(_) => return false,
+        };
+        let data = match &vrc.event_message.event.event_data {
+            EventData::Vrc(data) => data,
+            _ => return false,
+        };
+        let ser = event.event_message.serialize().expect("Can't serialize");
+        let seal = &data.validator_location_seal;
+        data.receipted_event_digest == data.receipted_event_digest.derivation.derive(&ser)
+            && self.validator_states.iter().any(|state| {
+                state.prefix == seal.prefix
+                    && seal.event_digest == seal.event_digest.derivation.derive(&state.last)
+                    && state
+                        .verify(&event.event_message.sign(vrc.signatures.clone()))
+                        .unwrap_or(false)
+            })
+    }
+}
+
+fn receipt(validator: &LogState, event: &SignedEventMessage) -> SignedEventMessage {
+    match validator.make_rct(event.event_message.clone()) {
+        Ok(Receipt::Transferable(rct)) => rct,
+        _ => panic!("Can't make receipt"),
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn log_state_invariants(ops in prop::collection::vec(op(), 1..32)) {
+        let mut model = Model::new();
+        model.check();
+        for op in ops {
+            model.apply(op);
+            model.check();
+        }
+    }
+}
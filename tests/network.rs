@@ -0,0 +1,573 @@
+mod common;
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::net::TcpListener;
+
+use common::{eventually, Node};
+use tda_standalone::{
+    config::Config,
+    control::{self, Session},
+    events::{Filter, Notification},
+    log_state,
+    receipt::{self, Receipt},
+    traits::{self, ConfigTrait},
+};
+
+// Address of peer accepting connections and never answering or closing
+// them, with count of connections it accepted.
+async fn silent_peer() -> (SocketAddr, Arc<AtomicUsize>) {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&accepted);
+    tokio::spawn(async move {
+        let mut held = vec![];
+        while let Ok((socket, _)) = listener.accept().await {
+            held.push(socket);
+            counter.fetch_add(1, Ordering::SeqCst);
+        }
+    });
+    (address, accepted)
+}
+
+// Exchange inception events of two nodes and their receipts.
+async fn introduce(a: &mut Node, b: &Node) {
+    let response = a.send_to(b).await;
+    assert_eq!(response, "");
+    // B responds with its inception and receipt of A's one, A sends back
+    // receipt of B's inception.
+    assert_eq!(a.receipts(0).await, 1);
+    assert_eq!(a.remote_state(b).await.sn, 0);
+    assert_eq!(b.remote_state(a).await.sn, 0);
+    eventually(|| async { b.receipts(0).await == 1 }).await;
+}
+
+#[tokio::test]
+async fn inception_exchange() {
+    let mut a = Node::start().await;
+    let b = Node::start().await;
+
+    introduce(&mut a, &b).await;
+
+    assert_eq!(a.kel().await.len(), 1);
+    assert_eq!(b.kel().await.len(), 1);
+    assert_eq!(
+        a.remote_state(&b).await.prefix,
+        b.kel().await[0].event_message.event.prefix
+    );
+}
+
+#[tokio::test]
+async fn rotation_is_receipted() {
+    let mut a = Node::start().await;
+    let b = Node::start().await;
+    introduce(&mut a, &b).await;
+
+    assert_eq!(a.command("ROT").await, "");
+    assert_eq!(a.sn().await, 1);
+    a.send_to(&b).await;
+
+    assert_eq!(b.remote_state(&a).await.sn, 1);
+    assert_eq!(a.receipts(1).await, 1);
+    assert_eq!(a.escrowed().await, 0);
+}
+
+#[tokio::test]
+async fn interaction_is_receipted() {
+    let mut a = Node::start().await;
+    let b = Node::start().await;
+    introduce(&mut a, &b).await;
+
+    assert_eq!(a.command("IXN hello").await, "");
+    a.send_to(&b).await;
+    assert_eq!(a.command("ROT").await, "");
+    a.send_to(&b).await;
+    assert_eq!(a.command("IXN world").await, "");
+    a.send_to(&b).await;
+
+    assert_eq!(a.sn().await, 3);
+    assert_eq!(b.remote_state(&a).await.sn, 3);
+    for sn in 1..=3 {
+        assert_eq!(a.receipts(sn).await, 1);
+    }
+}
+
+#[tokio::test]
+async fn recovery_rotation_supersedes_interactions() {
+    let mut a = Node::start().await;
+    let b = Node::start().await;
+    introduce(&mut a, &b).await;
+
+    // Interactions made with compromised keys reach the peer.
+    assert_eq!(a.command("IXN compromised").await, "");
+    a.send_to(&b).await;
+    assert_eq!(a.command("IXN compromised again").await, "");
+    a.send_to(&b).await;
+    assert_eq!(b.remote_state(&a).await.sn, 2);
+    let compromised = a.kel().await.remove(1);
+
+    assert_eq!(a.command("REC 1").await, "");
+    assert_eq!(a.sn().await, 1);
+    assert_eq!(a.kel().await.len(), 2);
+    assert_eq!(a.receipts(1).await, 0);
+
+    // Peer replaces superseded events with the recovery rotation.
+    a.send_to(&b).await;
+    let rot = a.kel().await.remove(1);
+    let state = b.remote_state(&a).await;
+    assert_eq!(state.sn, 1);
+    assert_eq!(state.last, rot.event_message.serialize().unwrap());
+    assert_eq!(a.receipts(1).await, 1);
+
+    // Superseded interaction can't be replayed.
+    let replayed = String::from_utf8(compromised.serialize().unwrap()).unwrap();
+    let selected = b.prefix.clone();
+    let response = b
+        .keri
+        .call(move |keri| keri.parse_event(&selected, &replayed))
+        .await;
+    assert!(response.is_empty());
+    assert_eq!(b.remote_state(&a).await.last, state.last);
+}
+
+#[tokio::test]
+async fn recovery_rotation_only_supersedes_interactions() {
+    let mut a = Node::start().await;
+
+    assert_eq!(
+        a.command("REC 1").await,
+        "Can't recover: no event to supersede at sn 1\n"
+    );
+    assert_eq!(a.command("ROT").await, "");
+    assert_eq!(a.command("IXN data").await, "");
+    assert_eq!(
+        a.command("REC 1").await,
+        "Can't recover: only interaction events can be superseded\n"
+    );
+    assert_eq!(a.command("REC 2").await, "");
+    assert_eq!(a.sn().await, 2);
+    assert!(a.own(|id| id.log.verify_kel()).await);
+}
+
+#[tokio::test]
+async fn abandoned_identifier_makes_no_more_events() {
+    let mut a = Node::start().await;
+    let b = Node::start().await;
+    introduce(&mut a, &b).await;
+
+    assert_eq!(a.command("ABN").await, "");
+    assert_eq!(a.sn().await, 1);
+    assert!(a.command("IDS").await.contains("Abandoned\n"));
+    assert_eq!(
+        a.command("ROT").await,
+        "Can't rotate: identifier is abandoned\n"
+    );
+    assert_eq!(
+        a.command("IXN data").await,
+        "Can't make interaction: identifier is abandoned\n"
+    );
+    assert_eq!(a.sn().await, 1);
+
+    // Peer learns about abandonment and receipts it.
+    a.send_to(&b).await;
+    assert!(log_state::is_abandoned(&b.remote_state(&a).await));
+    assert_eq!(a.receipts(1).await, 1);
+}
+
+#[tokio::test]
+async fn nontransferable_identifier_only_receipts() {
+    let mut config = Config::default();
+    config.identifier.transferable = false;
+    let mut n = Node::with_config(config).await;
+    let mut a = Node::start().await;
+
+    // Inception of non-transferable identifier is accepted with its receipt.
+    assert_eq!(a.send_to(&n).await, "");
+    assert_eq!(a.receipts(0).await, 1);
+    let state = a.remote_state(&n).await;
+    assert_eq!(state.prefix, n.kel().await[0].event_message.event.prefix);
+    assert!(!log_state::is_transferable(&state));
+
+    assert_eq!(
+        n.command("ROT").await,
+        "Can't rotate: non-transferable identifier can't make events\n"
+    );
+    assert_eq!(
+        n.command("IXN data").await,
+        "Can't make interaction: non-transferable identifier can't make events\n"
+    );
+    assert_eq!(n.sn().await, 0);
+}
+
+#[tokio::test]
+async fn establishment_only_identifier_makes_no_interactions() {
+    let mut config = Config::default();
+    config.identifier.traits = vec!["EO".into(), "DND".into()];
+    let mut a = Node::with_config(config).await;
+    let mut b = Node::start().await;
+    introduce(&mut a, &b).await;
+
+    assert!(a.command("IDS").await.contains("Traits: EO, DND\n"));
+    assert_eq!(
+        a.command("IXN data").await,
+        "Can't make interaction: establishment-only identifier can't make interaction events\n"
+    );
+    assert_eq!(a.command("ROT").await, "");
+    a.send_to(&b).await;
+    assert_eq!(b.remote_state(&a).await.sn, 1);
+
+    // Peers check events against traits declared in the inception, so
+    // interaction of establishment-only identifier isn't accepted either.
+    let declared = traits::declared(&a.kel().await);
+    assert_eq!(
+        declared,
+        vec![ConfigTrait::EstablishmentOnly, ConfigTrait::DoNotDelegate]
+    );
+    assert_eq!(b.command("IXN data").await, "");
+    let ixn = b.kel().await.remove(1);
+    assert!(traits::check(&declared, &ixn).is_err());
+    assert!(traits::check(&[], &ixn).is_ok());
+    assert!(traits::delegator(&ixn).is_none());
+}
+
+#[tokio::test]
+async fn events_are_filtered_by_kind_and_prefix() {
+    let mut a = Node::start().await;
+    let b = Node::start().await;
+    let mut subscription = a.keri.call(|keri| keri.subscribe()).await;
+
+    introduce(&mut a, &b).await;
+    assert_eq!(a.command("IXN hello").await, "");
+    let mut published = vec![];
+    while let Ok(notification) = subscription.try_recv() {
+        published.push(notification);
+    }
+
+    let matching = |query: &str| -> Vec<&Notification> {
+        let filter = Filter::from_query(query);
+        published.iter().filter(|n| filter.matches(n)).collect()
+    };
+    assert_eq!(matching("").len(), published.len());
+    // B's inception and A's interaction.
+    let kinds: Vec<&str> = matching("kind=event,remote_state")
+        .iter()
+        .map(|n| n.kind())
+        .collect();
+    assert_eq!(kinds, vec!["remote_state", "event"]);
+    // B's receipt of A's inception.
+    let receipts = matching(&format!("kind=receipt&prefix={}", a.prefix));
+    assert_eq!(receipts.len(), 1);
+    assert!(matches!(
+        receipts[0],
+        Notification::Receipt {
+            sn: 0,
+            receipts: 1,
+            ..
+        }
+    ));
+    assert!(matching(&format!("prefix={}", b.prefix))
+        .iter()
+        .all(|n| n.kind() == "remote_state"));
+    assert!(matching("kind=resolved").is_empty());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn failed_hook_is_retried_and_deliveries_are_logged() {
+    use std::fs;
+    use tda_standalone::hooks::{self, Hook, Target, Trigger};
+
+    let dir = common::temp_dir("hooks");
+    let log = dir.join("hooks.log");
+    let delivered = dir.join("delivered.json");
+    let hook = |prefix: &str, command: &[&str], retries| Hook {
+        trigger: Trigger::Event,
+        prefix: Some(prefix.to_string()),
+        target: Target::Command(command.iter().map(|arg| arg.to_string()).collect()),
+        retries,
+    };
+    let mut a = Node::start().await;
+    let save = format!("cat > {}", delivered.display());
+    let hooks = vec![
+        hook(&a.prefix, &["false"], 1),
+        hook(&a.prefix, &["sh", "-c", &save], 3),
+        hook("other", &["sh", "-c", &save], 3),
+    ];
+    let subscription = a.keri.call(|keri| keri.subscribe()).await;
+    tokio::spawn(hooks::run_hooks(hooks, subscription, Some(log.clone())));
+
+    assert_eq!(a.command("IXN hello").await, "");
+    let read_log = || -> Vec<serde_json::Value> {
+        fs::read_to_string(&log)
+            .unwrap_or_default()
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("Invalid delivery log"))
+            .collect()
+    };
+    eventually(|| async { read_log().len() == 3 }).await;
+
+    let deliveries = read_log();
+    let attempts = |hook: &str| -> Vec<(u64, bool)> {
+        deliveries
+            .iter()
+            .filter(|delivery| delivery["hook"] == hook)
+            .map(|delivery| {
+                assert_eq!(delivery["notification"]["kind"], "event");
+                assert_eq!(delivery["notification"]["sn"], 1);
+                let attempt = delivery["attempt"].as_u64().unwrap();
+                (attempt, delivery["delivered"].as_bool().unwrap())
+            })
+            .collect()
+    };
+    assert_eq!(attempts("false"), vec![(1, false), (2, false)]);
+    // Hook of other identifier, with the same command, isn't triggered.
+    assert_eq!(attempts(&format!("sh -c {}", save)), vec![(1, true)]);
+
+    let notification: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&delivered).unwrap()).unwrap();
+    assert_eq!(notification["prefix"], a.prefix.as_str());
+    assert_eq!(notification["event_type"], "Ixn");
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn interaction_mixing_payload_and_seals_is_refused() {
+    let mut a = Node::start().await;
+
+    assert_eq!(
+        a.command("IXN hello digest:world").await,
+        "Can't mix payload with seals\n"
+    );
+    assert_eq!(a.sn().await, 0);
+    assert_eq!(a.command("IXN hello world").await, "");
+    assert_eq!(a.command("IXN digest:hello digest:world").await, "");
+    assert_eq!(a.sn().await, 2);
+}
+
+#[tokio::test]
+async fn receipt_of_unknown_validator_is_rejected() {
+    let mut a = Node::start().await;
+    let b = Node::start().await;
+
+    // B learns A's KEL out of band and receipts its inception, but A knows
+    // nothing about B yet.
+    b.learn(&a).await;
+    let icp = a.kel().await.remove(0);
+    let rct = b.receipt(&icp).await;
+    assert_eq!(a.accept(rct).await, "");
+    // Receipt can't be verified, so it's neither stored nor escrowed.
+    assert_eq!(a.receipts(0).await, 0);
+    assert_eq!(a.escrowed().await, 0);
+
+    // Once A knows B's KEL, the same receipt verifies.
+    a.learn(&b).await;
+    let rct = b.receipt(&icp).await;
+    tda_standalone::send_receipt(a.address.to_string(), rct)
+        .await
+        .unwrap();
+    eventually(|| async { a.receipts(0).await == 1 }).await;
+}
+
+#[tokio::test]
+async fn resolved_kel_must_extend_known_one() {
+    let a = Node::start().await;
+    let mut b = Node::start().await;
+    let stale = b.kel().await;
+    assert_eq!(b.command("ROT").await, "");
+    a.learn(&b).await;
+
+    // Stale KEL served by introduction endpoint would undo B's rotation.
+    let prefix = b.prefix.clone();
+    let rolled_back = a
+        .keri
+        .call(move |keri| keri.process_kel(&prefix, &stale))
+        .await;
+    assert!(rolled_back.is_err());
+    assert_eq!(a.remote_state(&b).await.sn, 1);
+
+    // KEL adding events to the known one is accepted.
+    assert_eq!(b.command("IXN more").await, "");
+    a.learn(&b).await;
+    assert_eq!(a.remote_state(&b).await.sn, 2);
+}
+
+#[tokio::test]
+async fn duplicate_receipt_is_rejected() {
+    let mut a = Node::start().await;
+    let b = Node::start().await;
+    introduce(&mut a, &b).await;
+
+    // B's receipt of A's inception was already stored, the same validator
+    // can't add another one.
+    let icp = a.kel().await.remove(0);
+    let rct = b.receipt(&icp).await;
+    assert_eq!(a.accept(rct).await, "");
+    assert_eq!(a.receipts(0).await, 1);
+    assert_eq!(a.own(|id| id.log.sigs_map[&0].len()).await, 1);
+}
+
+#[tokio::test]
+async fn nontransferable_receipt_round_trip() {
+    let mut config = Config::default();
+    config.identifier.transferable = false;
+    let n = Node::with_config(config).await;
+    let mut a = Node::start().await;
+
+    let icp = a.kel().await.remove(0);
+    let rct = n.receipt(&icp).await;
+    let serialized = rct.serialize().unwrap();
+    let text = String::from_utf8(serialized.clone()).unwrap();
+    let (rest, parsed) = receipt::parse_nontransferable(&text).expect("Can't parse receipt");
+    assert!(rest.is_empty());
+    assert_eq!(parsed.serialize().unwrap(), serialized);
+    assert_eq!(
+        Receipt::Nontransferable(parsed.clone()).validators(),
+        vec![n.prefix.clone()]
+    );
+
+    // Validator's key is its prefix, so its KEL isn't needed to verify the
+    // receipt, which is counted once.
+    assert_eq!(a.accept(rct.clone()).await, "");
+    assert_eq!(a.receipts(0).await, 1);
+    assert_eq!(a.accept(rct).await, "");
+    assert_eq!(a.receipts(0).await, 1);
+
+    // Signature of the inception doesn't receipt other event.
+    assert_eq!(a.command("IXN data").await, "");
+    let mut moved = parsed;
+    moved.body.event.sn = 1;
+    assert_eq!(a.accept(Receipt::Nontransferable(moved)).await, "");
+    assert_eq!(a.receipts(1).await, 0);
+}
+
+#[tokio::test]
+async fn escrowed_receipt_is_recovered() {
+    let mut a = Node::start().await;
+    let mut b = Node::start().await;
+    introduce(&mut a, &b).await;
+
+    // B's receipts now seal its interaction event, which A hasn't seen.
+    assert_eq!(b.command("IXN unseen").await, "");
+    assert_eq!(a.command("IXN data").await, "");
+    a.send_to(&b).await;
+    assert_eq!(a.receipts(1).await, 0);
+    assert_eq!(a.escrowed().await, 1);
+
+    // Learning B's interaction event makes the escrowed receipt verifiable.
+    b.send_to(&a).await;
+    assert_eq!(a.remote_state(&b).await.sn, 1);
+    assert_eq!(a.receipts(1).await, 1);
+    assert_eq!(a.escrowed().await, 0);
+    assert_eq!(b.receipts(1).await, 1);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn control_socket_is_private_and_removed_when_done() {
+    use std::{fs, io, os::unix::fs::PermissionsExt};
+    use tda_standalone::{config::ListenConfig, metrics::Metrics};
+
+    let dir = common::temp_dir("control-socket");
+    let path = dir.join("tda.sock");
+    // Socket left over after a crash doesn't accept connections.
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+    let (listener, file) = control::bind_control_socket(&path).expect("Can't bind socket");
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    // Private directory the socket was bound in is gone.
+    let entries: Vec<_> = fs::read_dir(&dir).unwrap().collect();
+    assert_eq!(entries.len(), 1);
+
+    // Socket of running TDA isn't replaced.
+    let refused = control::bind_control_socket(&path)
+        .err()
+        .expect("Socket replaced");
+    assert_eq!(refused.kind(), io::ErrorKind::AddrInUse);
+
+    let a = Node::start().await;
+    tokio::spawn(control::serve_control_socket(
+        listener,
+        path.clone(),
+        a.keri.clone(),
+        a.prefix.clone(),
+        "127.0.0.1:0".into(),
+        Arc::new(Metrics::default()),
+    ));
+    let mut listen = ListenConfig::default();
+    listen.control_socket = Some(path.clone());
+    let response = control::send_command(&listen, "IDS").await.unwrap();
+    let response = String::from_utf8(response).unwrap();
+    assert!(
+        response.contains(&a.prefix),
+        "Unexpected response: {}",
+        response
+    );
+
+    drop(file);
+    assert!(!path.exists());
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn unresponsive_peer_times_out_without_holding_other_commands() {
+    let mut a = Node::start().await;
+    let (address, accepted) = silent_peer().await;
+
+    // `SEN` from another connection waits for the peer's response.
+    let (keri, prefix) = (a.keri.clone(), a.prefix.clone());
+    let sent = tokio::spawn(async move {
+        let mut session = Session::new(prefix, "127.0.0.1:0".into(), true);
+        let command = format!("SEN {} {}\n", address.ip(), address.port());
+        control::execute(&keri, &mut session, &command).await
+    });
+    eventually(|| async { accepted.load(Ordering::SeqCst) == 1 }).await;
+
+    let response = tokio::time::timeout(Duration::from_secs(1), a.command("IDS"))
+        .await
+        .expect("IDS waited for unresponsive peer");
+    assert!(
+        response.contains("Prefix:"),
+        "Unexpected response: {}",
+        response
+    );
+
+    // `SEN` itself fails once the peer doesn't respond in time.
+    let response = tokio::time::timeout(Duration::from_secs(10), sent)
+        .await
+        .expect("SEN waited for unresponsive peer")
+        .unwrap();
+    let response = String::from_utf8(response).unwrap();
+    assert!(
+        response.starts_with("Can't send event"),
+        "Unexpected response: {}",
+        response
+    );
+}
+
+#[tokio::test]
+async fn unresponsive_introduction_endpoint_times_out() {
+    let mut a = Node::start().await;
+    let (address, _) = silent_peer().await;
+
+    let url = format!("http://{}/oobi/{}/controller", address, a.prefix);
+    let response =
+        tokio::time::timeout(Duration::from_secs(10), a.command(&format!("RES {}", url)))
+            .await
+            .expect("RES waited for unresponsive endpoint");
+    assert!(
+        response.starts_with("Can't fetch KEL"),
+        "Unexpected response: {}",
+        response
+    );
+}